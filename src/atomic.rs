@@ -0,0 +1,109 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use colored::Colorize;
+use crate::lock::FileLock;
+
+
+/// `<file>.bak`, the content of the file before its last write
+pub fn backup_path(path: &Path) -> PathBuf{
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+
+/// Replace the content of a file without ever leaving it half written.
+///
+/// The content is written to a temporary file next to it, flushed to disk and renamed over the file,
+/// so an interrupted write leaves either the old or the new content. The old content is kept in `<file>.bak`.
+pub fn write(path: &Path, contents: &str) -> Result<(), anyhow::Error>{
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".tmp.{}", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| -> Result<(), anyhow::Error>{
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result{
+        let _ = fs::remove_file(&temp_path);
+        return Err(anyhow!("Failed to write {}: {}", path.display(), e));
+    }
+
+    if path.exists(){
+        fs::copy(path, backup_path(path))?;
+    }
+    if let Err(e) = fs::rename(&temp_path, path){
+        let _ = fs::remove_file(&temp_path);
+        return Err(anyhow!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+
+/// Read and parse a file written with [`write`] under a shared [`FileLock`].
+///
+/// When the file cannot be parsed the shared lock is released and the file is read again
+/// under the exclusive lock with [`read_or_restore`], so the backup is never restored over
+/// a file another process is writing.
+pub fn read<T, F>(path: &Path, parse: F) -> Result<T, anyhow::Error>
+    where F: Fn(&str) -> Result<T, anyhow::Error>
+{
+    let contents = {
+        let _lock = FileLock::shared(path)?;
+        read_contents(path)?
+    };
+    match parse(&contents){
+        Ok(value) => Ok(value),
+        Err(_) => {
+            let _lock = FileLock::exclusive(path)?;
+            read_or_restore(path, parse)
+        }
+    }
+}
+
+
+/// Read and parse a file written with [`write`], the caller holds its exclusive lock.
+///
+/// When the file cannot be parsed, e.g. it was truncated by a crash of an older version, the backup
+/// is used instead and restored over it. The unparsable file is kept as `<file>.corrupt`.
+/// A file that cannot be read at all is an error, only its content is considered corrupt.
+pub fn read_or_restore<T, F>(path: &Path, parse: F) -> Result<T, anyhow::Error>
+    where F: Fn(&str) -> Result<T, anyhow::Error>
+{
+    let error = match parse(&read_contents(path)?){
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    let backup = backup_path(path);
+    let backup_contents = match fs::read_to_string(&backup){
+        Ok(backup_contents) => backup_contents,
+        Err(_) => return Err(anyhow!("Failed to read {}: {}", path.display(), error)),
+    };
+    let value = match parse(&backup_contents){
+        Ok(value) => value,
+        Err(backup_error) => {
+            return Err(anyhow!("Failed to read {}: {}\nThe backup {} cannot be read either: {}", path.display(), error, backup.display(), backup_error));
+        }
+    };
+
+    eprintln!("{}", format!("{} cannot be read, restoring the backup {}: {}", path.display(), backup.display(), error).yellow());
+    let mut corrupt_name = path.file_name().unwrap_or_default().to_os_string();
+    corrupt_name.push(".corrupt");
+    if path.exists(){
+        fs::rename(path, path.with_file_name(corrupt_name))?;
+    }
+    write(path, &backup_contents)?;
+    Ok(value)
+}
+
+
+/// Content of a file without parsing it, a file replaced by [`write`] is never seen half written
+pub fn read_contents(path: &Path) -> Result<String, anyhow::Error>{
+    fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+}
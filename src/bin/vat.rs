@@ -5,7 +5,17 @@ use vat::package::Package;
 use vat::stack::{Stacks, Stack};
 use git2::Repository as GitRepository;
 use std::io::{self, Write}; 
-use vat::vat_repository::VatRepo;
+use vat::vat_repository::{VatRepo, VatRepos};
+use vat::lockfile::{LockFile, VAT_LOCK};
+use vat::context::ResolvedContext;
+use vat::shell::Shell;
+use vat::output;
+use vat::history::History;
+use vat::diff::ContextDiff;
+use vat::launch::LaunchReport;
+use vat::remote::RepositoryRemote;
+use vat::config::VatConfig;
+use std::path::PathBuf;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const MESSAGE: &str = "Vat is a lightweight package manager / environment manager";
@@ -16,6 +26,10 @@ const MESSAGE: &str = "Vat is a lightweight package manager / environment manage
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    #[arg(long="json", global = true, help = "Print the result as JSON, errors are printed as {\"error\": \"...\"}")]
+    json: bool,
+    #[arg(long="quiet", short='q', global = true, help = "Only print the result, without progress messages")]
+    quiet: bool,
 }
 
 #[derive(Subcommand)]
@@ -48,6 +62,13 @@ enum Commands {
     },
     #[command(name = "link", about = "Link a Vat package to a repository, without publishing")]
     Link,
+    #[command(name = "install", about = "Clone a Vat package from its git url and publish a version of it to the repository")]
+    Install{
+        #[arg(help = "The git url of the package")]
+        url: String,
+        #[arg(long="version", short='v', help = "The version tag to install, defaults to the latest version tag")]
+        version: Option<semver::Version>,
+    },
     #[command(name = "run", about = "Run a Vat package command")]
     Run{
         #[arg(required = false, help = "The command to run")]
@@ -58,9 +79,71 @@ enum Commands {
         package: Option<String>,
         #[arg(long="detach", short='d', help = "Run the command in the background")]
         detach: bool,
+        #[arg(long="locked", conflicts_with_all = ["append", "package"], help = "Run with the versions from vat.lock")]
+        locked: bool,
+        #[arg(long="clean", help = "Start from the base environment of the config instead of the current one")]
+        clean: bool,
+        #[arg(last = true, help = "Arguments passed to the command, after `--`")]
+        args: Vec<String>,
+    },
+    #[command(name = "lock", about = "Resolve a Vat package and write vat.lock")]
+    Lock{
+        #[arg(long="append", short='a', num_args = 1.., help = "Append packages to the environment")]
+        append: Option<Vec<String>>,
+        #[arg(long="package", short='p', help = "The package to resolve, defaults to the current directory")]
+        package: Option<String>,
+    },
+    #[command(name = "env", about = "Print the resolved environment as a shell script", args_conflicts_with_subcommands = true)]
+    Env{
+        #[command(subcommand)]
+        action: Option<EnvCommands>,
+        #[arg(long="append", short='a', num_args = 1.., help = "Append packages to the environment")]
+        append: Option<Vec<String>>,
+        #[arg(long="package", short='p', help = "The package to resolve")]
+        package: Option<String>,
+        #[arg(long="command", short='c', help = "Use the environments of this command of the main package")]
+        command: Option<String>,
+        #[arg(long="shell", short='s', help = "bash, zsh, fish, powershell or cmd, defaults to the current shell")]
+        shell: Option<Shell>,
+        #[arg(long="locked", conflicts_with_all = ["append", "package"], help = "Resolve with the versions from vat.lock")]
+        locked: bool,
+    },
+    #[command(name = "shell", about = "Start a shell inside the resolved environment")]
+    Shell{
+        #[arg(long="append", short='a', num_args = 1.., help = "Append packages to the environment")]
+        append: Option<Vec<String>>,
+        #[arg(long="package", short='p', help = "The package to resolve")]
+        package: Option<String>,
+        #[arg(long="command", short='c', help = "Use the environments of this command of the main package")]
+        command: Option<String>,
+        #[arg(long="shell", short='s', help = "bash, zsh, fish, powershell or cmd, defaults to the current shell")]
+        shell: Option<Shell>,
+        #[arg(long="locked", conflicts_with_all = ["append", "package"], help = "Resolve with the versions from vat.lock")]
+        locked: bool,
+        #[arg(long="clean", help = "Start from the base environment of the config instead of the current one")]
+        clean: bool,
+    },
+    #[command(name = "context", about = "Save and run resolved contexts")]
+    Context{
+        #[command(subcommand)]
+        action: ContextCommands,
+    },
+    #[command(name = "history", about = "List the launch history", args_conflicts_with_subcommands = true)]
+    History{
+        #[command(subcommand)]
+        action: Option<HistoryCommands>,
+        #[arg(long="package", short='p', help = "Only launches that resolved this package")]
+        package: Option<String>,
+        #[arg(long="user", short='u', help = "Only launches by this user")]
+        user: Option<String>,
+        #[arg(long="limit", short='n', default_value_t = 20, help = "Number of entries to show")]
+        limit: usize,
+    },
+    #[command(name = "repo", about = "List all Vat packages in the repositories")]
+    Repo{
+        #[command(subcommand)]
+        action: Option<RepoCommands>,
     },
-    #[command(name = "repo", about = "List all Vat packages in the repository")]
-    Repo,
     #[command(name = "stack", about = "Run a Vat stack")]
     Stack{
         #[arg(help = "The stack to run")]
@@ -71,12 +154,109 @@ enum Commands {
 
 
 
+}
+
+#[derive(Subcommand)]
+enum EnvCommands {
+    #[command(name = "diff", about = "Compare the packages and variables of two resolved environments")]
+    Diff{
+        #[arg(help = "A package request, a context file or a vat.lock")]
+        first: String,
+        #[arg(help = "A package request, a context file or a vat.lock")]
+        second: String,
+        #[arg(long="command", short='c', help = "Use the environments of this command of the main packages")]
+        command: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RepoCommands {
+    #[command(name = "add-remote", about = "Mirror a repository from a remote git repository")]
+    AddRemote{
+        #[arg(help = "The url of the git repository, a path to a local bare repository works")]
+        url: String,
+        #[arg(long="repository", short='r', help = "The repository to mirror into, created when it is not in the config, it cannot be the publish repository")]
+        repository: String,
+    },
+    #[command(name = "sync", about = "Pull the new releases of mirrored repositories from their remote")]
+    Sync{
+        #[arg(long="repository", short='r', help = "Only sync this repository, defaults to every repository with a remote")]
+        repository: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContextCommands {
+    #[command(name = "save", about = "Resolve a Vat package and save the context to a file")]
+    Save{
+        #[arg(help = "The file to save the context to")]
+        file: PathBuf,
+        #[arg(required = false, help = "The command of the main package")]
+        subcommand: Option<String>,
+        #[arg(long="append", short='a', num_args = 1.., help = "Append packages to the environment")]
+        append: Option<Vec<String>>,
+        #[arg(long="package", short='p', help = "The package to resolve")]
+        package: Option<String>,
+        #[arg(long="locked", conflicts_with_all = ["append", "package"], help = "Resolve with the versions from vat.lock")]
+        locked: bool,
+        #[arg(long="clean", help = "Start from the base environment of the config instead of the current one")]
+        clean: bool,
+    },
+    #[command(name = "run", about = "Run a command inside a saved context")]
+    Run{
+        #[arg(help = "The context file")]
+        file: PathBuf,
+        #[arg(required = false, help = "The program to run, defaults to the command saved in the context")]
+        cmd: Option<String>,
+        #[arg(long="detach", short='d', help = "Run the command in the background")]
+        detach: bool,
+        #[arg(last = true, help = "Arguments passed to the program, after `--`")]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommands {
+    #[command(name = "rerun", about = "Launch a history entry again with the same resolved context")]
+    Rerun{
+        #[arg(help = "The id of the history entry")]
+        id: u64,
+        #[arg(long="detach", short='d', help = "Run the command in the background")]
+        detach: bool,
+    },
 }
 
 fn main() -> Result<(), anyhow::Error> {
     let cli = Cli::parse();
+    output::set_quiet(cli.quiet);
+    output::set_json(cli.json);
+
+    let result = execute(cli.command);
+    if output::is_json(){
+        if let Err(e) = result{
+            output::json(&serde_json::json!({"error": e.to_string()}))?;
+            std::process::exit(1);
+        }
+    }
+    result
+}
+
 
-    match cli.command {
+/// Exit with the exit code of a launched program, the launch is printed with `--json`
+fn exit_with(launch_report: LaunchReport) -> Result<(), anyhow::Error> {
+    if output::is_json(){
+        output::json(&launch_report)?;
+    }else if launch_report.detached{
+        // the result of a detached launch, printed even with --quiet
+        let log_file = launch_report.log_file.as_ref().map(|log_file| log_file.display().to_string()).unwrap_or_default();
+        println!("{}", format!("Detached process {}, log: {}", launch_report.pid, log_file).yellow());
+    }
+    std::process::exit(launch_report.exit_code.unwrap_or(0));
+}
+
+
+fn execute(command: Option<Commands>) -> Result<(), anyhow::Error> {
+    match command {
         // Some(Commands::Test) => {
         //     let current_dir = std::env::current_dir()?;
         //     let package = Package::read(&current_dir)?;
@@ -86,13 +266,74 @@ fn main() -> Result<(), anyhow::Error> {
         //     Ok(())
         // }
 
-        Some(Commands::Repo) => {
-            let repository = VatRepo::init();
+        Some(Commands::Repo { action: Some(RepoCommands::AddRemote { url, repository }) }) => {
+            let mut vat_config = VatConfig::init()?;
+            let repository_name = repository;
+            // a sync would overwrite the versions published locally
+            if vat_config.get_publish_repository().is_some_and(|publish_repository| publish_repository.name == repository_name){
+                return Err(anyhow::anyhow!("Repository {} is the publish repository, mirror the remote into another repository", repository_name));
+            }
+            let repository_path = match vat_config.get_repositories().into_iter().find(|r| r.name == repository_name){
+                Some(repository) => repository.path,
+                None => {
+                    let repository_path = RepositoryRemote::default_repository_path(&repository_name)?;
+                    vat_config.add_repository(&repository_name, repository_path.clone());
+                    vat_config.save()?;
+                    output::status(format!("Added repository {} at {}", repository_name, repository_path.display()));
+                    repository_path
+                }
+            };
+            let vat_repo = VatRepo::open(&repository_name, &repository_path)?;
+            RepositoryRemote::add_remote(&vat_repo, &url)?;
+            if output::is_json(){
+                output::json(&serde_json::json!({"repository": repository_name, "path": repository_path, "remote": url}))?;
+            }
+            output::status(format!("{} {} {}", repository_name.green(), "mirrors".bright_black(), url));
+            Ok(())
+        }
+
+        Some(Commands::Repo { action: Some(RepoCommands::Sync { repository }) }) => {
+            let vat_repos = VatRepos::init()?;
+            let mut reports = vec![];
+            for vat_repo in vat_repos.search(repository.as_deref())?{
+                // without --repository only the mirrored repositories are synced
+                if repository.is_none() && RepositoryRemote::get_remote(vat_repo).is_none(){
+                    continue;
+                }
+                let report = RepositoryRemote::sync(vat_repo)?;
+                if report.from.as_ref() == Some(&report.to){
+                    output::status(format!("{} is up to date", report.repository.green()));
+                }else{
+                    output::status(format!("{} synced to {}", report.repository.green(), report.to.bright_black()));
+                    for published in &report.published{
+                        output::status(format!("   + {}", published));
+                    }
+                }
+                reports.push(report);
+            }
+            if reports.is_empty() && !output::is_json(){
+                println!("{}", "No repository has a remote, run `vat repo add-remote` first".yellow());
+            }
+            if output::is_json(){
+                output::json(&reports)?;
+            }
+            Ok(())
+        }
+
+        Some(Commands::Repo { action: None }) => {
+            let repository = VatRepos::init();
             match repository{
                 Ok(repository) => {
-                    repository.pretty_list();
+                    if output::is_json(){
+                        output::json(&repository.listing())?;
+                    }else{
+                        repository.pretty_list();
+                    }
                 }
                 Err(e) => {
+                    if output::is_json(){
+                        return Err(e);
+                    }
                     eprintln!("Error: {}", e);
                 }
             }
@@ -111,9 +352,15 @@ fn main() -> Result<(), anyhow::Error> {
             match repository{
                 Ok(mut repository) => {
                     repository.link_package(&read_package, &current_dir)?;
-                    println!("Package linked successfully to repository");
+                    if output::is_json(){
+                        output::json(&serde_json::json!({"name": read_package.get_name(), "path": current_dir}))?;
+                    }
+                    output::status("Package linked successfully to repository");
                 }
                 Err(e) => {
+                    if output::is_json(){
+                        return Err(e);
+                    }
                     eprintln!("Error initializing repository: {}", e);
                 }
             }
@@ -123,12 +370,17 @@ fn main() -> Result<(), anyhow::Error> {
         Some(Commands::Init) => {
             let current_dir = std::env::current_dir()?;
 
-            if let Err(e) = Package::init(current_dir, None) {
-
-                let error = format!("{}", e);
-                return Err(anyhow::anyhow!(error));
-            }else{
-                return Ok(());
+            match Package::init(current_dir.clone(), None) {
+                Err(e) => {
+                    let error = format!("{}", e);
+                    return Err(anyhow::anyhow!(error));
+                }
+                Ok(package) => {
+                    if output::is_json(){
+                        output::json(&serde_json::json!({"name": package.get_name(), "path": current_dir}))?;
+                    }
+                    return Ok(());
+                }
             }
 
 
@@ -141,9 +393,12 @@ fn main() -> Result<(), anyhow::Error> {
 
             }else{
                 // create folder
-                let package_dir = current_dir.join(name);
-                std::fs::create_dir_all(package_dir).unwrap();  
-                println!("{}", format!("Vat package initialized").green());
+                let package_dir = current_dir.join(&name);
+                std::fs::create_dir_all(&package_dir).unwrap();  
+                if output::is_json(){
+                    output::json(&serde_json::json!({"name": name, "path": package_dir}))?;
+                }
+                output::status(format!("Vat package initialized").green());
                 return Ok(());
 
             }
@@ -155,10 +410,17 @@ fn main() -> Result<(), anyhow::Error> {
 
 
                 Ok(package) => {
-                    println!("Package: {:?}", package);
+                    if output::is_json(){
+                        output::json(&package)?;
+                    }else{
+                        package.pretty_print();
+                    }
                     return Ok(());
                 }
                 Err(e) => {
+                    if output::is_json(){
+                        return Err(anyhow::anyhow!("Error reading Vat: {}", e));
+                    }
                     eprintln!("{}", format!("Error reading Vat: {}", e).red());
                     return Err(anyhow::anyhow!("Error reading Vat"));
                 }
@@ -175,12 +437,18 @@ fn main() -> Result<(), anyhow::Error> {
             match repository{
                 Ok(mut repository) => {
                     let print_message = format!("Publishing package {}, version {} to repository", read_package.get_name(), read_package.get_current_version());
-                    println!("{}", print_message.yellow());
-                    println!("{}", format!("Please wait while we publish the package...").yellow());
+                    output::status(print_message.yellow());
+                    output::status(format!("Please wait while we publish the package...").yellow());
                     repository.publish_package(&read_package, &current_dir, &message)?;
-                    println!("{}", format!("Package published successfully to repository").green());
+                    if output::is_json(){
+                        output::json(&serde_json::json!({"name": read_package.get_name(), "version": read_package.get_current_version(), "message": message}))?;
+                    }
+                    output::status(format!("Package published successfully to repository").green());
                 }
                 Err(e) => {
+                    if output::is_json(){
+                        return Err(e);
+                    }
                     eprintln!("Error initializing repository: {}", e);
                 }
 
@@ -188,7 +456,19 @@ fn main() -> Result<(), anyhow::Error> {
             Ok(())
         },
 
+        Some(Commands::Install { url, version }) => {
+            let package = Package::install(&url, version)?;
+            if output::is_json(){
+                output::json(&serde_json::json!({"name": package.get_name(), "version": package.get_version(), "url": url}))?;
+            }
+            output::status(format!("Package {} {} installed", package.get_name(), package.get_version()).green());
+            Ok(())
+        },
+
         Some(Commands::Up { major, minor, patch }) => {
+            if output::is_json(){
+                return Err(anyhow::anyhow!("vat up asks for confirmation and does not support --json"));
+            }
             let current_dir = std::env::current_dir().unwrap();
 
             if Package::is_vat_package(&current_dir) {
@@ -293,10 +573,108 @@ fn main() -> Result<(), anyhow::Error> {
             }
         },
 
-        Some(Commands::Run { subcommand, append, package, detach }) => {
+        Some(Commands::Run { subcommand, append, package, detach, locked, clean, args }) => {
 
-            let _result = Package::run(subcommand.unwrap().as_str(), package, append, detach)?;
+            let subcommand = match subcommand{
+                Some(subcommand) => subcommand,
+                None => return Err(anyhow::anyhow!("The command to run is required")),
+            };
+            let launch_report = Package::run(subcommand.as_str(), package, append, args, detach, locked, clean)?;
+            exit_with(launch_report)
+        }
+        Some(Commands::Lock { append, package }) => {
+            let current_dir = std::env::current_dir()?;
+            if !Package::is_vat_package(&current_dir){
+                return Err(anyhow::anyhow!("vat.toml not found in the current directory"));
+            }
 
+            let resolution = Package::resolve(package, append)?;
+            let lock_file = LockFile::from_resolution(&resolution);
+            lock_file.save(&current_dir)?;
+
+            if output::is_json(){
+                return output::json(&lock_file);
+            }
+            for locked_package in &lock_file.packages{
+                println!("   {} - {}", locked_package.name, locked_package.version.to_string().bright_black());
+            }
+            output::status(format!("Locked {} packages to {}", lock_file.packages.len(), VAT_LOCK).green());
+            Ok(())
+        }
+        Some(Commands::Env { action: Some(EnvCommands::Diff { first, second, command }), .. }) => {
+            output::set_quiet(true);
+            let first_context = ContextDiff::resolve_source(&first, command.as_deref())?;
+            let second_context = ContextDiff::resolve_source(&second, command.as_deref())?;
+            let diff = ContextDiff::from_contexts(&first_context, &second_context);
+            if output::is_json(){
+                return output::json(&diff);
+            }
+            diff.pretty_print();
+            Ok(())
+        }
+        Some(Commands::Env { action: None, append, package, command, shell, locked }) => {
+            // stdout only holds the script so it can be eval'd
+            output::set_quiet(true);
+            let context = Package::resolve_context(command.as_deref(), package, append, locked, false)?;
+            if output::is_json(){
+                return output::json(&context);
+            }
+            let shell = shell.unwrap_or_else(Shell::detect);
+            print!("{}", shell.script(&context.environment_variables, &context.unset_variables));
+            Ok(())
+        }
+        Some(Commands::Shell { append, package, command, shell, locked, clean }) => {
+            let context = Package::resolve_context(command.as_deref(), package, append, locked, clean)?;
+            let shell = shell.unwrap_or_else(Shell::detect);
+            let exit_code = shell.spawn(&context)?;
+            std::process::exit(exit_code);
+        }
+        Some(Commands::Context { action }) => {
+            match action{
+                ContextCommands::Save { file, subcommand, append, package, locked, clean } => {
+                    let context = Package::resolve_context(subcommand.as_deref(), package, append, locked, clean)?;
+                    context.save(&file)?;
+                    if output::is_json(){
+                        output::json(&context)?;
+                    }
+                    output::status(format!("Context saved to {}", file.display()).green());
+                }
+                ContextCommands::Run { file, cmd, detach, args } => {
+                    let context = ResolvedContext::read(&file)?;
+                    let launch_report = context.run(cmd.as_deref(), &args, detach)?;
+                    return exit_with(launch_report);
+                }
+            }
+            Ok(())
+        }
+        Some(Commands::History { action, package, user, limit }) => {
+            match action{
+                Some(HistoryCommands::Rerun { id, detach }) => {
+                    let entry = History::get(id)?;
+                    let launch_report = entry.context.run(Some(&entry.program), &entry.args, detach)?;
+                    return exit_with(launch_report);
+                }
+                None => {
+                    let entries = History::read()?;
+                    let entries = entries.iter()
+                        .filter(|entry| package.as_ref().map_or(true, |package| {
+                            entry.context.packages.iter().any(|p| &p.name == package || &format!("{}/{}", p.name, p.version) == package)
+                        }))
+                        .filter(|entry| user.as_ref().map_or(true, |user| &entry.user == user))
+                        .collect::<Vec<_>>();
+                    let skip = entries.len().saturating_sub(limit);
+                    let entries = entries.into_iter().skip(skip).collect::<Vec<_>>();
+                    if output::is_json(){
+                        return output::json(&entries);
+                    }
+                    if entries.is_empty(){
+                        output::status("No launches found in the history");
+                    }
+                    for entry in entries{
+                        History::pretty_print(entry);
+                    }
+                }
+            }
             Ok(())
         }
         Some(Commands::Stack { stack }) => {
@@ -306,8 +684,11 @@ fn main() -> Result<(), anyhow::Error> {
                 return Err(anyhow::anyhow!("Stack not found"));
             }
             let stack = stack.unwrap();
-            let _result = Package::run_stack(stack.clone(), None)?;
-            Ok(())
+            let exit_code = Package::run_stack(stack.clone(), None)?;
+            if output::is_json(){
+                output::json(&serde_json::json!({"stack": stack.name, "exit_code": exit_code}))?;
+            }
+            std::process::exit(exit_code);
         }
         None => {
             // println!("No command provided");
@@ -1,17 +1,48 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
 use dirs_next::{config_dir, document_dir};
 use std::fs;
+use std::time::Duration;
+use crate::atomic;
+use crate::lock::FileLock;
+use crate::environment::Environment;
 
 
 const CONFIG_FILE_NAME: &str = "vat.config";
+const DEFAULT_LOCK_TIMEOUT: u64 = 30;
+
+/// Variables kept from the current environment by clean launches, `*` matches any suffix
+const DEFAULT_CLEAN_ENVIRONMENT: &[&str] = &[
+    "HOME", "USER", "LOGNAME", "SHELL", "TERM", "DISPLAY", "WAYLAND_DISPLAY", "XAUTHORITY", "XDG_RUNTIME_DIR",
+    "LANG", "LANGUAGE", "LC_*", "TZ",
+    "USERNAME", "USERPROFILE", "SYSTEMROOT", "WINDIR", "COMSPEC", "PATHEXT", "TEMP", "TMP", "APPDATA", "LOCALAPPDATA",
+];
+
+
+/// A named repository searched when resolving packages
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryConfig{
+    pub name: String,
+    pub path: PathBuf,
+}
 
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VatConfig{
+    /// repository used when `repositories` is not set
     pub repository_path: Option<PathBuf>,   
     pub packages_path: Option<PathBuf>,
-
+    /// repositories searched in order when resolving packages, e.g. local, show, site
+    pub repositories: Option<Vec<RepositoryConfig>>,
+    /// name of the repository packages are published and linked to, defaults to the first repository
+    pub publish_repository: Option<String>,
+    /// variables kept from the current environment by clean launches, `*` matches any suffix
+    pub clean_environment: Option<Vec<String>>,
+    /// seconds to wait for another process to release a repository, registry or stacks lock
+    pub lock_timeout: Option<u64>,
+    /// `PATH` of clean launches, before the packages are applied
+    pub clean_path: Option<String>,
 }
 
 impl VatConfig {
@@ -37,6 +68,11 @@ impl VatConfig {
         VatConfig{
             repository_path: Some(default_repo_path),
             packages_path: Some(default_packages_path),
+            repositories: None,
+            publish_repository: None,
+            clean_environment: None,
+            lock_timeout: None,
+            clean_path: None,
         }
     }
 
@@ -54,18 +90,25 @@ impl VatConfig {
                     let config_path = app_dir.join(CONFIG_FILE_NAME);
                     let config = VatConfig::default();
                     let config_str = serde_json::to_string(&config).unwrap();
-                    fs::write(config_path, config_str).unwrap();
+                    atomic::write(&config_path, &config_str)?;
                     return Ok(config);
                 }
             } else{
                 let config_path = app_dir.join(CONFIG_FILE_NAME);
                 if config_path.exists(){
-                    let config = serde_json::from_str(&fs::read_to_string(config_path).unwrap()).unwrap();
+                    // the timeout of the locks is read from the config, restoring the config waits for its
+                    // exclusive lock with the default timeout instead
+                    let parse = |config_str: &str| Ok(serde_json::from_str::<VatConfig>(config_str)?);
+                    if let Ok(config) = parse(&atomic::read_contents(&config_path)?){
+                        return Ok(config);
+                    }
+                    let _lock = FileLock::exclusive_with_timeout(&config_path, Duration::from_secs(DEFAULT_LOCK_TIMEOUT))?;
+                    let config = atomic::read_or_restore(&config_path, parse)?;
                     return Ok(config);
                 }else{
                     let config = VatConfig::default();
                     let config_str = serde_json::to_string(&config).unwrap();
-                    fs::write(config_path, config_str).unwrap();
+                    atomic::write(&config_path, &config_str)?;
                     return Ok(config);
                 }
             }
@@ -74,18 +117,91 @@ impl VatConfig {
     }
 
     
+    /// Path of the repository packages are published to
     pub fn get_repository_path(&self) -> Option<PathBuf> {
-        self.repository_path.clone()
+        self.get_publish_repository().map(|repository| repository.path)
+    }
+
+    /// Repositories in search order, `repository_path` named `local` when no repositories are configured
+    pub fn get_repositories(&self) -> Vec<RepositoryConfig> {
+        match &self.repositories{
+            Some(repositories) if !repositories.is_empty() => repositories.clone(),
+            _ => match &self.repository_path{
+                Some(repository_path) => vec![RepositoryConfig{name: "local".to_string(), path: repository_path.clone()}],
+                None => vec![],
+            },
+        }
+    }
+
+    pub fn get_publish_repository(&self) -> Option<RepositoryConfig> {
+        let repositories = self.get_repositories();
+        match &self.publish_repository{
+            Some(name) => repositories.into_iter().find(|repository| &repository.name == name),
+            None => repositories.into_iter().next(),
+        }
+    }
+
+    /// Directory packages are cloned to by `vat install`
+    pub fn get_packages_path(&self) -> Option<PathBuf> {
+        self.packages_path.clone()
+    }
+
+    /// Add a repository at the end of the search order
+    pub fn add_repository(&mut self, name: &str, path: PathBuf) {
+        let mut repositories = self.get_repositories();
+        repositories.push(RepositoryConfig{name: name.to_string(), path});
+        self.repositories = Some(repositories);
     }
 
     pub fn set_repository_path(&mut self, path: PathBuf) {
         self.repository_path = Some(path);
     }
 
+    pub fn get_clean_environment(&self) -> Vec<String> {
+        match &self.clean_environment{
+            Some(clean_environment) => clean_environment.clone(),
+            None => DEFAULT_CLEAN_ENVIRONMENT.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    pub fn get_lock_timeout(&self) -> u64 {
+        self.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT)
+    }
+
+    pub fn get_clean_path(&self) -> String {
+        if let Some(clean_path) = &self.clean_path{
+            return clean_path.clone();
+        }
+        if cfg!(target_os = "windows"){
+            let system_root = std::env::var("SYSTEMROOT").unwrap_or("C:\\Windows".to_string());
+            format!("{}\\system32;{}", system_root, system_root)
+        }else{
+            "/usr/local/bin:/usr/bin:/bin".to_string()
+        }
+    }
+
+    /// Base environment of clean launches, the whitelisted variables of the current environment and `PATH`.
+    /// Variables a shell cannot set, like the `BASH_FUNC_name%%` of exported bash functions, are left out.
+    pub fn clean_base_environment(&self) -> BTreeMap<String, String> {
+        let clean_environment = self.get_clean_environment();
+        let is_kept = |name: &str| clean_environment.iter().any(|pattern| {
+            match pattern.strip_suffix('*'){
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            }
+        });
+
+        let mut environment_variables: BTreeMap<String, String> = std::env::vars()
+            .filter(|(name, _)| is_kept(name) && Environment::is_valid_variable_name(name))
+            .collect();
+        environment_variables.insert("PATH".to_string(), self.get_clean_path());
+        environment_variables
+    }
+
     pub fn save(&self) -> Result<(), anyhow::Error> {
         let config_path = VatConfig::get_app_dir().unwrap().join(CONFIG_FILE_NAME);
         let config_str = serde_json::to_string(&self).unwrap();
-        fs::write(config_path, config_str).unwrap();
+        atomic::write(&config_path, &config_str)?;
         Ok(())
     }
 
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+use crate::solver::{PackageRole, Resolution};
+use crate::output;
+use crate::platform;
+use crate::launch::{Launch, LaunchReport};
+use crate::history::History;
+use crate::config::VatConfig;
+use crate::package::Hook;
+use crate::expand::ExpandContext;
+use crate::environment::Environment;
+
+
+/// Outcome of resolving a package request, saved to a file so the exact
+/// environment can be launched again later without touching the repository.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedContext{
+    pub created_on: DateTime<Utc>,
+    /// name of the command in the main package vat.toml
+    pub command: Option<String>,
+    /// executable launched by the command
+    pub program: Option<String>,
+    /// default arguments of the command
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// package and command hooks run before the program, expanded
+    #[serde(default)]
+    pub pre_hooks: Vec<Hook>,
+    /// command and package hooks run after the program, expanded
+    #[serde(default)]
+    pub post_hooks: Vec<Hook>,
+    pub packages: Vec<ContextPackage>,
+    pub environment_variables: BTreeMap<String, String>,
+    /// variables removed from the environment of the launched program
+    #[serde(default)]
+    pub unset_variables: Vec<String>,
+    /// separator of the list variables like `PATH`, as configured in the packages
+    #[serde(default)]
+    pub separators: BTreeMap<String, String>,
+    /// the program does not inherit the environment of vat, it starts from the clean base environment
+    /// of the config where it is launched, `environment_variables` only holds the variables set by the packages
+    #[serde(default)]
+    pub clean: bool,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContextPackage{
+    pub name: String,
+    pub version: semver::Version,
+    pub root: PathBuf,
+    pub role: PackageRole,
+    pub env: Option<Vec<String>>,
+    /// repository the version was resolved from
+    #[serde(default)]
+    pub repository: Option<String>,
+}
+
+
+impl ResolvedContext{
+
+    /// Apply the environment of the resolved packages, the environments of the
+    /// main package are selected by `command` when it is given.
+    /// A `clean` context, or a command with `inherit_env = false`, starts from the base environment of the config.
+    pub fn from_resolution(resolution: &Resolution, command: Option<&str>, clean: bool) -> Result<Self, anyhow::Error>{
+        let main_package = match resolution.main(){
+            Some(main_package) => main_package,
+            None => return Err(anyhow!("Failed to resolve the main package")),
+        };
+
+        let package_hooks = main_package.package.hooks.clone().unwrap_or_default();
+
+        let (program, main_env, cmd_args, inherit_env, pre_hooks, post_hooks) = match command{
+            Some(command) => {
+                match main_package.package.get_cmd(command){
+                    Some(cmd) => {
+                        // package hooks wrap the hooks of the command
+                        let mut pre_hooks = package_hooks.pre.clone().unwrap_or_default();
+                        pre_hooks.extend(cmd.pre.clone().unwrap_or_default());
+                        let mut post_hooks = cmd.post.clone().unwrap_or_default();
+                        post_hooks.extend(package_hooks.post.clone().unwrap_or_default());
+                        (Some(cmd.command.clone()), cmd.env.clone(), cmd.args.clone().unwrap_or_default(), cmd.inherit_env.unwrap_or(true), pre_hooks, post_hooks)
+                    }
+                    None if main_package.package.command.as_ref().is_some_and(|cmds| cmds.contains_key(command)) => {
+                        return Err(anyhow!("Command {} of package {} is not available on {}/{}", command, main_package.name, platform::name(), platform::arch()));
+                    }
+                    None => return Err(anyhow!("Command {} not found in package {}", command, main_package.name)),
+                }
+            }
+            None => (None, None, vec![], true, package_hooks.pre.unwrap_or_default(), package_hooks.post.unwrap_or_default()),
+        };
+
+        let clean = clean || !inherit_env;
+        let base_environment = if clean{
+            VatConfig::init()?.clean_base_environment()
+        }else{
+            BTreeMap::new()
+        };
+        let mut environment = if clean{
+            Environment::from_base(base_environment.clone())
+        }else{
+            Environment::new()
+        };
+
+        // dependencies first, then the main package, then the append packages
+        resolution.process_env(&mut environment, main_env)?;
+
+        // args and hooks see the environment the program is launched with
+        let expand_context = ExpandContext::from_resolution(resolution);
+        let expand = |value: &str| expand_context.expand(value, &main_package.package, &main_package.path, |name| environment.get(name));
+        let mut args = vec![];
+        for arg in cmd_args{
+            args.push(expand(&arg)?);
+        }
+
+        let expand_hook = |hook: &Hook| -> Result<Hook, anyhow::Error>{
+            let command = expand(&hook.command)?;
+            let mut args = vec![];
+            for arg in hook.args.clone().unwrap_or_default(){
+                args.push(expand(&arg)?);
+            }
+            Ok(Hook{command, args: Some(args)})
+        };
+        let pre_hooks = pre_hooks.iter().map(expand_hook).collect::<Result<Vec<Hook>, anyhow::Error>>()?;
+        let post_hooks = post_hooks.iter().map(expand_hook).collect::<Result<Vec<Hook>, anyhow::Error>>()?;
+        let Environment{variables: mut environment_variables, unset_variables, separators, ..} = environment;
+        // HOME, USER, DISPLAY... of the base are not saved, they are read again where the context is launched
+        environment_variables.retain(|name, value| base_environment.get(name) != Some(value));
+
+        let packages = resolution.packages.iter().map(|resolved_package| ContextPackage{
+            name: resolved_package.name.clone(),
+            version: resolved_package.version.clone(),
+            root: resolved_package.path.clone(),
+            role: resolved_package.role.clone(),
+            env: resolved_package.env.clone(),
+            repository: resolved_package.repository.clone(),
+        }).collect();
+
+        Ok(Self{
+            created_on: Utc::now(),
+            command: command.map(|c| c.to_string()),
+            program,
+            args,
+            pre_hooks,
+            post_hooks,
+            packages,
+            environment_variables,
+            unset_variables: unset_variables.into_iter().collect(),
+            separators,
+            clean,
+        })
+    }
+
+
+    pub fn read(context_path: &Path) -> Result<Self, anyhow::Error>{
+        if !context_path.exists(){
+            return Err(anyhow!("Context file not found: {}", context_path.display()));
+        }
+        let context_str = fs::read_to_string(context_path)?;
+        let context: ResolvedContext = serde_json::from_str(&context_str)?;
+        let variables = context.environment_variables.keys().chain(context.unset_variables.iter());
+        if let Some(variable) = variables.into_iter().find(|variable| !Environment::is_valid_variable_name(variable)){
+            return Err(anyhow!("{}: invalid variable name `{}`", context_path.display(), variable));
+        }
+        Ok(context)
+    }
+
+
+    pub fn save(&self, context_path: &Path) -> Result<(), anyhow::Error>{
+        let context_str = serde_json::to_string_pretty(self)?;
+        fs::write(context_path, context_str)?;
+        Ok(())
+    }
+
+
+    pub fn main(&self) -> Option<&ContextPackage>{
+        self.packages.iter().find(|p| p.role == PackageRole::Main)
+    }
+
+
+    /// `VAT_*` variables describing the context, set in shells and launched programs
+    pub fn vat_variables(&self) -> BTreeMap<String, String>{
+        let mut variables = BTreeMap::new();
+
+        let packages = self.packages.iter()
+            .map(|p| format!("{}/{}", p.name, p.version))
+            .collect::<Vec<String>>();
+        variables.insert("VAT_PACKAGES".to_string(), packages.join(" "));
+
+        if let Some(main_package) = self.main(){
+            variables.insert("VAT_MAIN_PACKAGE".to_string(), main_package.name.clone());
+            variables.insert("VAT_MAIN_VERSION".to_string(), main_package.version.to_string());
+            variables.insert("VAT_MAIN_ROOT".to_string(), main_package.root.to_string_lossy().to_string());
+        }
+        if let Some(command) = &self.command{
+            variables.insert("VAT_COMMAND".to_string(), command.clone());
+        }
+
+        for package in &self.packages{
+            let name = package.name.to_uppercase().replace('-', "_");
+            variables.insert(format!("VAT_{}_VERSION", name), package.version.to_string());
+            variables.insert(format!("VAT_{}_ROOT", name), package.root.to_string_lossy().to_string());
+        }
+        variables
+    }
+
+
+    /// Short description of the main and append packages for shell prompts
+    pub fn prompt_label(&self) -> String{
+        let packages = self.packages.iter()
+            .filter(|p| p.role != PackageRole::Dependency)
+            .map(|p| format!("{}/{}", p.name, p.version))
+            .collect::<Vec<String>>();
+        format!("vat {}", packages.join(" "))
+    }
+
+
+    /// Variables set for the launched programs, on top of the clean base environment of the config for a clean context
+    pub fn launch_environment(&self) -> Result<BTreeMap<String, String>, anyhow::Error>{
+        if !self.clean{
+            return Ok(self.environment_variables.clone());
+        }
+        let mut environment_variables = VatConfig::init()?.clean_base_environment();
+        for variable in &self.unset_variables{
+            environment_variables.remove(variable);
+        }
+        environment_variables.extend(self.environment_variables.clone());
+        Ok(environment_variables)
+    }
+
+
+    /// Launch of the program of the context, or of `program` when given, which replaces
+    /// the program of the context and its default arguments. `args` are passed after them.
+    pub fn launch(&self, program: Option<&str>, args: &[String]) -> Result<Launch, anyhow::Error>{
+        let (program, mut program_args) = match program{
+            Some(program) => (program.to_string(), vec![]),
+            None => match &self.program{
+                Some(program) => (program.clone(), self.args.clone()),
+                None => return Err(anyhow!("No command to run in the context")),
+            },
+        };
+        program_args.extend(args.iter().cloned());
+        let mut launch = Launch::new(&program, program_args, self.launch_environment()?);
+        launch.unset_variables = self.unset_variables.clone();
+        launch.clean = self.clean;
+        Ok(launch)
+    }
+
+
+    /// Run hooks one after the other inside the resolved environment,
+    /// the first hook that fails aborts with an error.
+    /// `exit_code` of the main program is given to post hooks as `VAT_EXIT_CODE`.
+    pub fn run_hooks(&self, stage: &str, hooks: &[Hook], exit_code: Option<i32>) -> Result<(), anyhow::Error>{
+        for hook in hooks{
+            let args = hook.args.clone().unwrap_or_default();
+            let mut launch = Launch::new(&hook.command, args, self.launch_environment()?);
+            launch.unset_variables = self.unset_variables.clone();
+            launch.clean = self.clean;
+            if let Some(exit_code) = exit_code{
+                launch.environment_variables.insert("VAT_EXIT_CODE".to_string(), exit_code.to_string());
+            }
+
+            let message = format!("Running {} hook: {} {}", stage, launch.program, launch.args.join(" "));
+            output::status(message.bright_black());
+
+            let finished_process = match launch.run(){
+                Ok(finished_process) => finished_process,
+                Err(e) => return Err(anyhow!(format!("{} hook failed to start, {}", stage, e).red())),
+            };
+            if finished_process.exit_code != 0{
+                let message = format!("{} hook `{}` failed with exit code {}", stage, hook.command, finished_process.exit_code);
+                return Err(anyhow!(message.red()));
+            }
+        }
+        Ok(())
+    }
+
+
+    /// Run the program inside the resolved environment and wait for it to exit,
+    /// a detached program is not waited on and has no exit code.
+    pub fn run(&self, program: Option<&str>, args: &[String], detach: bool) -> Result<LaunchReport, anyhow::Error>{
+        let launch = self.launch(program, args)?;
+
+        self.run_hooks("pre", &self.pre_hooks, None)?;
+
+        let message = format!("Running Command: {} {}", launch.program, launch.args.join(" "));
+        output::status(message.green());
+
+        if !detach{
+            // the command inherits stdin, stdout and stderr
+            match launch.run(){
+                Ok(finished_process) => {
+                    History::record(self, &launch, Some(finished_process.pid), Some(finished_process.exit_code), false);
+                    self.run_hooks("post", &self.post_hooks, Some(finished_process.exit_code))?;
+                    Ok(LaunchReport{
+                        program: launch.program,
+                        args: launch.args,
+                        pid: finished_process.pid,
+                        exit_code: Some(finished_process.exit_code),
+                        detached: false,
+                        log_file: None,
+                    })
+                }
+                Err(e) => Err(anyhow!(e.to_string().red())),
+            }
+        }else{
+            if !self.post_hooks.is_empty(){
+                output::status("Post hooks are not run for detached commands".yellow());
+            }
+            match launch.spawn_detached(){
+                Ok(detached_process) => {
+                    History::record(self, &launch, Some(detached_process.pid), None, true);
+                    Ok(LaunchReport{
+                        program: launch.program,
+                        args: launch.args,
+                        pid: detached_process.pid,
+                        exit_code: None,
+                        detached: true,
+                        log_file: Some(detached_process.log_file),
+                    })
+                }
+                Err(e) => Err(anyhow!(e.to_string().red())),
+            }
+        }
+    }
+}
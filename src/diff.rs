@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use colored::Colorize;
+use serde::Serialize;
+use crate::context::ResolvedContext;
+use crate::lockfile::{LockFile, VAT_LOCK};
+use crate::package::Package;
+use crate::vat_repository::VatRepos;
+
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum PackageChange{
+    Added{name: String, version: semver::Version},
+    Removed{name: String, version: semver::Version},
+    Changed{name: String, from: semver::Version, to: semver::Version},
+}
+
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum VariableChange{
+    Added{name: String, value: String},
+    Removed{name: String, value: String},
+    /// unset in the second context, set or left to the environment in the first
+    Unset{name: String},
+    /// unset in the first context, left to the environment in the second
+    Restored{name: String},
+    /// `added` and `removed` hold the individual entries of list variables like `PATH`
+    Changed{name: String, from: String, to: String, added: Vec<String>, removed: Vec<String>},
+}
+
+
+/// Differences between two resolved contexts, from the first to the second
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextDiff{
+    pub packages: Vec<PackageChange>,
+    pub variables: Vec<VariableChange>,
+}
+
+impl ContextDiff{
+
+    /// Resolve one side of a diff, `source` is a saved context file, a `vat.lock` file or
+    /// a directory holding one, otherwise a package request like `houdini/20.5.1`.
+    pub fn resolve_source(source: &str, command: Option<&str>) -> Result<ResolvedContext, anyhow::Error>{
+        let path = PathBuf::from(source);
+        if path.is_dir() && LockFile::exists(&path){
+            let resolution = LockFile::read(&path)?.resolve(&VatRepos::init()?)?;
+            return ResolvedContext::from_resolution(&resolution, command, false);
+        }
+        if path.is_file(){
+            if path.file_name().is_some_and(|name| name == VAT_LOCK){
+                let package_path = path.parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+                let resolution = LockFile::read(&package_path)?.resolve(&VatRepos::init()?)?;
+                return ResolvedContext::from_resolution(&resolution, command, false);
+            }
+            return ResolvedContext::read(&path);
+        }
+        let resolution = Package::resolve(Some(source.to_string()), None)?;
+        ResolvedContext::from_resolution(&resolution, command, false)
+    }
+
+
+    pub fn from_contexts(first: &ResolvedContext, second: &ResolvedContext) -> Self{
+        let mut packages = vec![];
+        for package in &first.packages{
+            match second.packages.iter().find(|p| p.name == package.name){
+                Some(other) if other.version != package.version => {
+                    packages.push(PackageChange::Changed{name: package.name.clone(), from: package.version.clone(), to: other.version.clone()});
+                }
+                Some(_) => {}
+                None => packages.push(PackageChange::Removed{name: package.name.clone(), version: package.version.clone()}),
+            }
+        }
+        for package in &second.packages{
+            if !first.packages.iter().any(|p| p.name == package.name){
+                packages.push(PackageChange::Added{name: package.name.clone(), version: package.version.clone()});
+            }
+        }
+
+        let names = first.environment_variables.keys()
+            .chain(second.environment_variables.keys())
+            .chain(first.unset_variables.iter())
+            .chain(second.unset_variables.iter())
+            .collect::<BTreeSet<&String>>();
+        let mut variables = vec![];
+        for name in names{
+            let first_unset = first.unset_variables.contains(name);
+            let second_unset = second.unset_variables.contains(name);
+            match (first.environment_variables.get(name), second.environment_variables.get(name)){
+                (Some(from), Some(to)) if from != to => {
+                    // only variables a package changed as a list are split, each side with the separator it configured
+                    let from_separator = first.separators.get(name).or(second.separators.get(name));
+                    let to_separator = second.separators.get(name).or(first.separators.get(name));
+                    let (added, removed) = match (from_separator, to_separator){
+                        (Some(from_separator), Some(to_separator)) => {
+                            let from_entries = from.split(from_separator.as_str()).collect::<Vec<&str>>();
+                            let to_entries = to.split(to_separator.as_str()).collect::<Vec<&str>>();
+                            (
+                                to_entries.iter().filter(|entry| !from_entries.contains(entry)).map(|entry| entry.to_string()).collect(),
+                                from_entries.iter().filter(|entry| !to_entries.contains(entry)).map(|entry| entry.to_string()).collect(),
+                            )
+                        }
+                        _ => (vec![], vec![]),
+                    };
+                    variables.push(VariableChange::Changed{name: name.clone(), from: from.clone(), to: to.clone(), added, removed});
+                }
+                (Some(_), Some(_)) => {}
+                (_, None) if second_unset && !first_unset => variables.push(VariableChange::Unset{name: name.clone()}),
+                (Some(value), None) => variables.push(VariableChange::Removed{name: name.clone(), value: value.clone()}),
+                (None, Some(value)) => variables.push(VariableChange::Added{name: name.clone(), value: value.clone()}),
+                (None, None) if first_unset && !second_unset => variables.push(VariableChange::Restored{name: name.clone()}),
+                (None, None) => {}
+            }
+        }
+
+        Self{packages, variables}
+    }
+
+
+    pub fn is_empty(&self) -> bool{
+        self.packages.is_empty() && self.variables.is_empty()
+    }
+
+
+    pub fn pretty_print(&self){
+        if self.is_empty(){
+            println!("{}", "No differences".green());
+            return;
+        }
+
+        if !self.packages.is_empty(){
+            println!("{}", "Packages:".green());
+            for change in &self.packages{
+                match change{
+                    PackageChange::Added{name, version} => println!("   {}", format!("+ {} {}", name, version).green()),
+                    PackageChange::Removed{name, version} => println!("   {}", format!("- {} {}", name, version).red()),
+                    PackageChange::Changed{name, from, to} => println!("   {} {} {} -> {}", "~".yellow(), name, from.to_string().bright_black(), to.to_string().yellow()),
+                }
+            }
+        }
+
+        if !self.variables.is_empty(){
+            println!("{}", "Variables:".green());
+            for change in &self.variables{
+                match change{
+                    VariableChange::Added{name, value} => println!("   {} {}", format!("+ {}", name).green(), value.bright_black()),
+                    VariableChange::Removed{name, value} => println!("   {} {}", format!("- {}", name).red(), value.bright_black()),
+                    VariableChange::Unset{name} => println!("   {} {}", format!("- {}", name).red(), "unset".bright_black()),
+                    VariableChange::Restored{name} => println!("   {} {} {}", "~".yellow(), name, "no longer unset".bright_black()),
+                    VariableChange::Changed{name, from, to, added, removed} => {
+                        if added.is_empty() && removed.is_empty(){
+                            // a single value, or a list only reordered
+                            println!("   {} {} {} -> {}", "~".yellow(), name, from.bright_black(), to.yellow());
+                        }else{
+                            println!("   {} {}", "~".yellow(), name);
+                            for entry in added{
+                                println!("      {}", format!("+ {}", entry).green());
+                            }
+                            for entry in removed{
+                                println!("      {}", format!("- {}", entry).red());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
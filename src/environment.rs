@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use anyhow::anyhow;
+use colored::Colorize;
+use crate::package::{EnvAction, Package};
+use crate::expand::ExpandContext;
+use crate::output;
+
+
+/// A single environment entry with its value expanded, ready to be applied
+#[derive(Debug, Clone)]
+pub struct EnvOperation{
+    /// name of the entry in the vat.toml `[environment]` table
+    pub name: String,
+    pub variable: String,
+    pub action: EnvAction,
+    pub value: String,
+    pub separator: String,
+}
+
+
+/// Environment built by applying operations one after the other.
+///
+/// Packages are applied in the order of the resolution, dependencies first, then the main
+/// package, then the append packages. Within a package the entries selected by a command
+/// are applied in the order of the command `env` list, otherwise every entry in the order of vat.toml.
+#[derive(Debug, Clone)]
+pub struct Environment{
+    pub variables: BTreeMap<String, String>,
+    /// variables removed from the environment of the launched program
+    pub unset_variables: BTreeSet<String>,
+    /// separator of the list variables, from the last prepend, append, remove or dedupe
+    pub separators: BTreeMap<String, String>,
+    /// variables not set by an operation are read from the current environment
+    pub inherit_env: bool,
+}
+
+impl Default for Environment{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl Environment{
+
+    /// Environment on top of the current one
+    pub fn new() -> Self{
+        Self{variables: BTreeMap::new(), unset_variables: BTreeSet::new(), separators: BTreeMap::new(), inherit_env: true}
+    }
+
+    /// Environment starting from `variables` only, the current environment is not used
+    pub fn from_base(variables: BTreeMap<String, String>) -> Self{
+        Self{variables, unset_variables: BTreeSet::new(), separators: BTreeMap::new(), inherit_env: false}
+    }
+
+
+    /// Value of a variable before the next operation, `None` when it is not set.
+    /// A variable unset by an earlier operation is not set, even if it is in the current environment.
+    pub fn get(&self, variable: &str) -> Option<String>{
+        if let Some(value) = self.variables.get(variable){
+            return Some(value.clone());
+        }
+        if self.unset_variables.contains(variable) || !self.inherit_env{
+            return None;
+        }
+        std::env::var(variable).ok()
+    }
+
+
+    /// `[A-Za-z_][A-Za-z0-9_]*`, the names every shell accepts.
+    /// Names are written unquoted in the scripts printed by `vat env`, anything else is rejected.
+    pub fn is_valid_variable_name(name: &str) -> bool{
+        let mut chars = name.chars();
+        match chars.next(){
+            Some(first) if first.is_ascii_alphabetic() || first == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            _ => false,
+        }
+    }
+
+
+    fn set(&mut self, variable: &str, value: String){
+        self.unset_variables.remove(variable);
+        self.variables.insert(variable.to_string(), value);
+    }
+
+
+    /// Apply the selected entries of a package in order, see [`Package::get_envs`]
+    pub fn apply_package(&mut self, package: &Package, envs: Option<Vec<String>>, root_path: &Path, expand_context: &ExpandContext) -> Result<(), anyhow::Error>{
+        for (env_name, env) in package.get_envs(envs){
+            let action = match &env.action{
+                Some(action) => action.clone(),
+                None => continue,
+            };
+            if !Self::is_valid_variable_name(&env.variable){
+                return Err(anyhow!("{}: invalid variable name `{}` in environment {}", package.get_name(), env.variable, env_name));
+            }
+            output::status(format!("Resolving Environment Variable: {}", env_name.yellow()));
+            let value = expand_context.expand(&env.value, package, root_path, |name| self.get(name))?;
+            self.apply(&EnvOperation{
+                name: env_name,
+                variable: env.variable.clone(),
+                action,
+                value,
+                separator: env.get_separator().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+
+    pub fn apply(&mut self, operation: &EnvOperation){
+        let variable = &operation.variable;
+        let existing_value = self.get(variable);
+        let separator = operation.separator.as_str();
+        if matches!(operation.action, EnvAction::Prepend | EnvAction::Append | EnvAction::Remove | EnvAction::Dedupe){
+            self.separators.insert(variable.clone(), operation.separator.clone());
+        }
+
+        match operation.action{
+            EnvAction::Prepend => {
+                let new_value = Self::join_values(&operation.value, &existing_value.unwrap_or_default(), separator);
+                output::status(format!("   Prepended {} - {}", variable.bright_cyan(), new_value.bright_black()));
+                self.set(variable, new_value);
+            }
+            EnvAction::Append => {
+                let new_value = Self::join_values(&existing_value.unwrap_or_default(), &operation.value, separator);
+                output::status(format!("   Appended {} - {}", variable.bright_cyan(), new_value.bright_black()));
+                self.set(variable, new_value);
+            }
+            EnvAction::Define => {
+                output::status(format!("   Defined {} - {}", variable.bright_cyan(), operation.value.bright_black()));
+                self.set(variable, operation.value.clone());
+            }
+            EnvAction::Default => {
+                match existing_value{
+                    Some(existing_value) => {
+                        output::status(format!("   Kept {} - {}", variable.bright_cyan(), existing_value.bright_black()));
+                    }
+                    None => {
+                        output::status(format!("   Defaulted {} - {}", variable.bright_cyan(), operation.value.bright_black()));
+                        self.set(variable, operation.value.clone());
+                    }
+                }
+            }
+            // a variable that is not set stays unset instead of being defined as empty
+            EnvAction::Remove | EnvAction::Dedupe if existing_value.is_none() => {
+                output::status(format!("   Skipped {} - not set", variable.bright_cyan()));
+            }
+            EnvAction::Remove => {
+                let new_value = Self::remove_values(&existing_value.unwrap_or_default(), &operation.value, separator);
+                output::status(format!("   Removed {} from {} - {}", operation.value.yellow(), variable.bright_cyan(), new_value.bright_black()));
+                self.set(variable, new_value);
+            }
+            EnvAction::Dedupe => {
+                let new_value = Self::dedupe_values(&existing_value.unwrap_or_default(), separator);
+                output::status(format!("   Deduplicated {} - {}", variable.bright_cyan(), new_value.bright_black()));
+                self.set(variable, new_value);
+            }
+            EnvAction::Unset => {
+                output::status(format!("   Unset {}", variable.bright_cyan()));
+                self.variables.remove(variable);
+                self.unset_variables.insert(variable.clone());
+            }
+        }
+    }
+
+
+    /// Join two values of a list variable, an empty side does not leave a dangling separator
+    pub fn join_values(first: &str, second: &str, separator: &str) -> String{
+        let first = first.trim_end_matches(separator);
+        let second = second.trim_start_matches(separator);
+        if first.is_empty(){
+            second.to_string()
+        }else if second.is_empty(){
+            first.to_string()
+        }else{
+            format!("{}{}{}", first, separator, second)
+        }
+    }
+
+    /// Entries of a list variable without the empty ones and without duplicates, the first occurrence is kept
+    pub fn dedupe_values(value: &str, separator: &str) -> String{
+        let mut entries: Vec<&str> = vec![];
+        for entry in value.split(separator){
+            if !entry.is_empty() && !entries.contains(&entry){
+                entries.push(entry);
+            }
+        }
+        entries.join(separator)
+    }
+
+    /// Entries of a list variable that are not in `remove`
+    pub fn remove_values(value: &str, remove: &str, separator: &str) -> String{
+        let remove = remove.split(separator).collect::<Vec<&str>>();
+        value.split(separator)
+            .filter(|entry| !entry.is_empty() && !remove.contains(entry))
+            .collect::<Vec<&str>>()
+            .join(separator)
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::path::PathBuf;
+    use crate::package::Environtment;
+
+    fn package(name: &str, envs: &[(&str, &str, &str, EnvAction)]) -> Package{
+        let mut package = Package::default(name.to_string());
+        for (env_name, variable, value, action) in envs{
+            let mut env = Environtment::from(variable.to_string(), value.to_string(), Some(action.clone()));
+            env.separator = Some(":".to_string());
+            package.append_env(env_name, env);
+        }
+        package
+    }
+
+    fn apply(environment: &mut Environment, package: &Package, envs: Option<Vec<String>>){
+        let root_path = PathBuf::from(format!("/pkg/{}", package.get_name()));
+        environment.apply_package(package, envs, &root_path, &ExpandContext::new()).unwrap();
+    }
+
+    fn base() -> Environment{
+        Environment::from_base(BTreeMap::from([("PATH".to_string(), "/usr/bin:/bin".to_string())]))
+    }
+
+    #[test]
+    fn packages_prepend_in_resolution_order(){
+        let usd = package("usd", &[("PATH", "PATH", "{root}/bin", EnvAction::Prepend)]);
+        let houdini = package("houdini", &[("PATH", "PATH", "{root}/bin", EnvAction::Prepend)]);
+        let mut environment = base();
+        apply(&mut environment, &usd, None);
+        apply(&mut environment, &houdini, None);
+        assert_eq!(environment.variables["PATH"], "/pkg/houdini/bin:/pkg/usd/bin:/usr/bin:/bin");
+    }
+
+    #[test]
+    fn entries_of_a_package_targeting_the_same_variable_accumulate(){
+        let houdini = package("houdini", &[
+            ("a_bin", "PATH", "{root}/bin", EnvAction::Prepend),
+            ("b_python", "PATH", "{root}/python/bin", EnvAction::Prepend),
+            ("c_tools", "PATH", "{root}/tools", EnvAction::Append),
+        ]);
+        let mut environment = base();
+        apply(&mut environment, &houdini, None);
+        assert_eq!(environment.variables["PATH"], "/pkg/houdini/python/bin:/pkg/houdini/bin:/usr/bin:/bin:/pkg/houdini/tools");
+    }
+
+    #[test]
+    fn entries_are_applied_in_vat_toml_order(){
+        let houdini: Package = toml::from_str(r#"
+            [package]
+            name = "houdini"
+            version = "20.5.1"
+            authors = []
+
+            [environment.z_bin]
+            variable = "PATH"
+            value = "{root}/bin"
+            action = "Prepend"
+            separator = ":"
+
+            [environment.a_python]
+            variable = "PATH"
+            value = "{root}/python/bin"
+            action = "Prepend"
+            separator = ":"
+        "#).unwrap();
+        let mut environment = base();
+        apply(&mut environment, &houdini, None);
+        assert_eq!(environment.variables["PATH"], "/pkg/houdini/python/bin:/pkg/houdini/bin:/usr/bin:/bin");
+    }
+
+    #[test]
+    fn selected_entries_are_applied_in_the_given_order(){
+        let houdini = package("houdini", &[
+            ("bin", "PATH", "{root}/bin", EnvAction::Prepend),
+            ("python", "PATH", "{root}/python/bin", EnvAction::Prepend),
+        ]);
+        let mut environment = base();
+        apply(&mut environment, &houdini, Some(vec!["python".to_string(), "bin".to_string()]));
+        assert_eq!(environment.variables["PATH"], "/pkg/houdini/bin:/pkg/houdini/python/bin:/usr/bin:/bin");
+    }
+
+    #[test]
+    fn remove_and_dedupe_compose_with_other_packages(){
+        let system = package("system", &[("PATH", "PATH", "/opt/usd/bin:/usr/bin", EnvAction::Prepend)]);
+        let usd = package("usd", &[
+            ("a_remove", "PATH", "/opt/usd/bin", EnvAction::Remove),
+            ("b_bin", "PATH", "{root}/bin", EnvAction::Prepend),
+            ("c_dedupe", "PATH", "", EnvAction::Dedupe),
+        ]);
+        let mut environment = base();
+        apply(&mut environment, &system, None);
+        apply(&mut environment, &usd, None);
+        assert_eq!(environment.variables["PATH"], "/pkg/usd/bin:/usr/bin:/bin");
+    }
+
+    #[test]
+    fn remove_and_dedupe_leave_an_unset_variable_unset(){
+        let usd = package("usd", &[
+            ("a_remove", "PYTHONPATH", "/opt/usd/python", EnvAction::Remove),
+            ("b_dedupe", "PYTHONPATH", "", EnvAction::Dedupe),
+        ]);
+        let mut environment = base();
+        environment.unset_variables.insert("PYTHONPATH".to_string());
+        apply(&mut environment, &usd, None);
+        assert!(!environment.variables.contains_key("PYTHONPATH"));
+        assert!(environment.unset_variables.contains("PYTHONPATH"));
+    }
+
+    #[test]
+    fn unset_hides_the_variable_from_later_entries(){
+        let first = package("first", &[("PYTHONHOME", "PYTHONHOME", "", EnvAction::Unset)]);
+        let second = package("second", &[("PYTHONHOME", "PYTHONHOME", "{root}", EnvAction::Default)]);
+        let mut environment = Environment::new();
+        environment.variables.insert("PYTHONHOME".to_string(), "/usr".to_string());
+        apply(&mut environment, &first, None);
+        assert!(environment.unset_variables.contains("PYTHONHOME"));
+        apply(&mut environment, &second, None);
+        assert_eq!(environment.variables["PYTHONHOME"], "/pkg/second");
+        assert!(environment.unset_variables.is_empty());
+    }
+
+    #[test]
+    fn unset_variables_are_not_expanded(){
+        let usd = package("usd", &[
+            ("a_unset", "VAT_T_HOME", "", EnvAction::Unset),
+            ("b_ref", "VAT_T_REF", "${VAT_T_HOME}/ref", EnvAction::Define),
+        ]);
+        let mut environment = Environment::new();
+        environment.variables.insert("VAT_T_HOME".to_string(), "/parent".to_string());
+        let error = environment.apply_package(&usd, None, &PathBuf::from("/pkg/usd"), &ExpandContext::new()).unwrap_err();
+        assert_eq!(error.to_string(), "usd: `${VAT_T_HOME}` in `${VAT_T_HOME}/ref` is not set");
+
+        let nuke = package("nuke", &[
+            ("a_unset", "VAT_T_HOME", "", EnvAction::Unset),
+            ("b_home", "VAT_T_HOME", "{root}", EnvAction::Default),
+            ("c_ref", "VAT_T_REF", "${VAT_T_HOME}/ref", EnvAction::Define),
+        ]);
+        let mut environment = base();
+        environment.variables.insert("VAT_T_HOME".to_string(), "/parent".to_string());
+        apply(&mut environment, &nuke, None);
+        assert_eq!(environment.variables["VAT_T_REF"], "/pkg/nuke/ref");
+    }
+
+    #[test]
+    fn variable_names_are_validated(){
+        for name in ["PATH", "_vat", "OCIO_2", "a"]{
+            assert!(Environment::is_valid_variable_name(name), "{}", name);
+        }
+        for name in ["", "2PATH", "BASH_FUNC_ls%%", "PATH; rm -rf ~", "ProgramFiles(x86)", "$(id)"]{
+            assert!(!Environment::is_valid_variable_name(name), "{}", name);
+        }
+        let houdini = package("houdini", &[("bin", "PATH;id", "{root}/bin", EnvAction::Prepend)]);
+        let error = base().apply_package(&houdini, None, &PathBuf::from("/pkg/houdini"), &ExpandContext::new()).unwrap_err();
+        assert_eq!(error.to_string(), "houdini: invalid variable name `PATH;id` in environment bin");
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use crate::package::Package;
+use crate::platform;
+use crate::environment::Environment;
+use crate::solver::Resolution;
+
+
+/// Values available to the placeholders of environment values.
+///
+/// Supported placeholders:
+/// - `{root}`, `{name}`, `{version}`, `{major}`, `{minor}`, `{patch}` of the package being processed
+/// - `{platform}` of the current host, `windows`, `linux` or `macos`
+/// - `{packages.<name>.root}` and `{packages.<name>.version}` of any package in the resolved context
+/// - `${VAR}` the value of a variable in the environment being resolved, see [`Environment::get`],
+///   a variable that is not set, or was unset by an earlier entry, is an error
+///
+/// `{{` and `}}` are literal braces, `$${` is a literal `${`. A `${...}` that is not a variable name,
+/// e.g. `${VAR:-default}` meant for the shell running a command, is kept as it is.
+#[derive(Debug, Clone)]
+pub struct ExpandContext{
+    packages: BTreeMap<String, (semver::Version, PathBuf)>,
+}
+
+impl Default for ExpandContext{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl ExpandContext{
+    pub fn new() -> Self{
+        Self{packages: BTreeMap::new()}
+    }
+
+    pub fn from_resolution(resolution: &Resolution) -> Self{
+        let mut packages = BTreeMap::new();
+        for resolved_package in &resolution.packages{
+            packages.insert(resolved_package.name.clone(), (resolved_package.version.clone(), resolved_package.path.clone()));
+        }
+        Self{packages}
+    }
+
+
+    /// Expand the placeholders of `value`, `lookup` gives the value of `${VAR}`
+    pub fn expand<F>(&self, value: &str, package: &Package, root_path: &Path, lookup: F) -> Result<String, anyhow::Error>
+        where F: Fn(&str) -> Option<String>
+    {
+        let chars = value.chars().collect::<Vec<char>>();
+        let mut expanded = String::new();
+        let mut i = 0;
+
+        while i < chars.len(){
+            match &chars[i..]{
+                ['$', '$', '{', ..] => {
+                    expanded.push_str("${");
+                    i += 3;
+                }
+                ['$', '{', rest @ ..] => {
+                    let close = rest.iter().position(|c| *c == '}');
+                    let name = close.map(|close| rest[..close].iter().collect::<String>());
+                    match (close, name){
+                        (Some(close), Some(name)) if Environment::is_valid_variable_name(&name) => {
+                            let variable_value = match lookup(&name){
+                                Some(variable_value) => variable_value,
+                                None => return Err(anyhow!("{}: `${{{}}}` in `{}` is not set", package.get_name(), name, value)),
+                            };
+                            expanded.push_str(&variable_value);
+                            i += close + 3;
+                        }
+                        (Some(close), _) => {
+                            expanded.extend(&chars[i..i + close + 3]);
+                            i += close + 3;
+                        }
+                        (None, _) => {
+                            expanded.extend(&chars[i..]);
+                            i = chars.len();
+                        }
+                    }
+                }
+                ['{', '{', ..] => {
+                    expanded.push('{');
+                    i += 2;
+                }
+                ['}', '}', ..] => {
+                    expanded.push('}');
+                    i += 2;
+                }
+                ['{', rest @ ..] => {
+                    let close = match rest.iter().position(|c| *c == '}'){
+                        Some(close) => close,
+                        None => return Err(anyhow!("Unclosed placeholder in `{}`", value)),
+                    };
+                    let placeholder = rest[..close].iter().collect::<String>();
+                    expanded.push_str(&self.placeholder(placeholder.trim(), package, root_path)?);
+                    i += close + 2;
+                }
+                [c, ..] => {
+                    expanded.push(*c);
+                    i += 1;
+                }
+                [] => break,
+            }
+        }
+        Ok(expanded)
+    }
+
+
+    fn placeholder(&self, placeholder: &str, package: &Package, root_path: &Path) -> Result<String, anyhow::Error>{
+        let version = package.get_version();
+        let value = match placeholder{
+            "root" => root_path.to_string_lossy().to_string(),
+            "name" => package.get_name().to_string(),
+            "version" => version.to_string(),
+            "major" => version.major.to_string(),
+            "minor" => version.minor.to_string(),
+            "patch" => version.patch.to_string(),
+            "platform" => platform::name().to_string(),
+            _ => {
+                let parts = placeholder.split('.').collect::<Vec<&str>>();
+                match parts.as_slice(){
+                    ["packages", package_name, field] => {
+                        let (package_version, package_root) = match self.packages.get(*package_name){
+                            Some(resolved) => resolved,
+                            None => return Err(anyhow!("{}: `{{{}}}` refers to {} which is not in the resolved context", package.get_name(), placeholder, package_name)),
+                        };
+                        match *field{
+                            "root" => package_root.to_string_lossy().to_string(),
+                            "version" => package_version.to_string(),
+                            _ => return Err(anyhow!("{}: unknown placeholder `{{{}}}`", package.get_name(), placeholder)),
+                        }
+                    }
+                    _ => return Err(anyhow!("{}: unknown placeholder `{{{}}}`", package.get_name(), placeholder)),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn expand(value: &str) -> Result<String, anyhow::Error>{
+        let mut package = Package::default("usd".to_string());
+        package.package_info.version = semver::Version::new(24, 1, 0);
+        let mut expand_context = ExpandContext::new();
+        expand_context.packages.insert("ocio".to_string(), (semver::Version::new(2, 3, 0), PathBuf::from("/pkg/ocio")));
+        let variables = BTreeMap::from([("PXR_ROOT".to_string(), "/opt/usd".to_string())]);
+        expand_context.expand(value, &package, Path::new("/pkg/usd"), |name| variables.get(name).cloned())
+    }
+
+    #[test]
+    fn placeholders_of_the_package_and_the_context(){
+        assert_eq!(expand("{root}/bin").unwrap(), "/pkg/usd/bin");
+        assert_eq!(expand("{name}-{version}-{major}.{minor}.{patch}").unwrap(), "usd-24.1.0-24.1.0");
+        assert_eq!(expand("{packages.ocio.root}/config.ocio").unwrap(), "/pkg/ocio/config.ocio");
+        assert_eq!(expand("{ packages.ocio.version }").unwrap(), "2.3.0");
+        assert_eq!(expand("{platform}").unwrap(), platform::name());
+    }
+
+    #[test]
+    fn variables_are_expanded(){
+        assert_eq!(expand("${PXR_ROOT}/lib:{root}/lib").unwrap(), "/opt/usd/lib:/pkg/usd/lib");
+    }
+
+    #[test]
+    fn unknown_variables_and_placeholders_are_errors(){
+        assert_eq!(expand("${TYPO}/bin").unwrap_err().to_string(), "usd: `${TYPO}` in `${TYPO}/bin` is not set");
+        assert_eq!(expand("{rot}/bin").unwrap_err().to_string(), "usd: unknown placeholder `{rot}`");
+        assert!(expand("{packages.nuke.root}").is_err());
+        assert_eq!(expand("{root/bin").unwrap_err().to_string(), "Unclosed placeholder in `{root/bin`");
+    }
+
+    #[test]
+    fn escapes_and_shell_syntax_are_kept(){
+        assert_eq!(expand("{{root}}").unwrap(), "{root}");
+        assert_eq!(expand("$${PXR_ROOT}").unwrap(), "${PXR_ROOT}");
+        assert_eq!(expand("echo ${HOME-unset} ${#PATH} ${PXR_ROOT}").unwrap(), "echo ${HOME-unset} ${#PATH} /opt/usd");
+        assert_eq!(expand("echo ${HOME").unwrap(), "echo ${HOME");
+        assert_eq!(expand("cost $5").unwrap(), "cost $5");
+    }
+}
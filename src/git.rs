@@ -8,6 +8,8 @@ pub trait Git {
     fn get_remotes(&self) -> Result<Vec<String>, anyhow::Error>;
     fn create_main_branch(&self) -> Result<String, anyhow::Error>;
     fn git_ignore(&self, path: &PathBuf) -> Result<(), anyhow::Error>;  
+    fn fetch_tags(&self) -> Result<(), anyhow::Error>;
+    fn checkout_tag(&self, tag: &str) -> Result<(), anyhow::Error>;
 }
 
 impl Git for GitRepository {
@@ -44,6 +46,24 @@ impl Git for GitRepository {
         file.write_all(ignore_raw_stirng.as_bytes())?;
         Ok(())
     }
+
+    fn fetch_tags(&self) -> Result<(), anyhow::Error> {
+        // git fetch origin --tags
+        let mut remote = self.find_remote("origin")?;
+        remote.fetch(&["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"], None, None)?;
+        Ok(())
+    }
+
+    fn checkout_tag(&self, tag: &str) -> Result<(), anyhow::Error> {
+        // git checkout <tag>, the head is detached
+        let commit = match self.find_reference(&format!("refs/tags/{}", tag)){
+            Ok(reference) => reference.peel_to_commit()?,
+            Err(_) => return Err(anyhow::anyhow!("Tag {} not found", tag)),
+        };
+        self.checkout_tree(commit.as_object(), Some(git2::build::CheckoutBuilder::new().force()))?;
+        self.set_head_detached(commit.id())?;
+        Ok(())
+    }
 }
 
 pub struct GitTags{
@@ -56,6 +76,12 @@ impl GitTags{
         Self{tags}
     }
 
+    /// Only the tags that are versions, other tags are ignored
+    pub fn from_versions(tags: Vec<String>) -> Self{
+        let tags = tags.iter().filter_map(|tag| semver::Version::parse(tag).ok()).collect::<Vec<_>>();
+        Self{tags}
+    }
+
     pub fn get_latest(&self) -> Option<semver::Version> {
         self.tags.iter().max().cloned()
     }
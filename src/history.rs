@@ -0,0 +1,166 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+use crate::config::VatConfig;
+use crate::context::ResolvedContext;
+use crate::launch::Launch;
+use crate::lock::FileLock;
+
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+
+/// One launch recorded in the history
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry{
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub host: String,
+    pub cwd: PathBuf,
+    pub program: String,
+    pub args: Vec<String>,
+    pub pid: Option<u32>,
+    /// exit code of the program, `None` when it was detached
+    pub exit_status: Option<i32>,
+    pub detached: bool,
+    pub context: ResolvedContext,
+}
+
+impl HistoryEntry{
+    /// `name/version` of every package of the launch
+    pub fn packages(&self) -> Vec<String>{
+        self.context.packages.iter()
+            .map(|p| format!("{}/{}", p.name, p.version))
+            .collect()
+    }
+}
+
+
+/// Append-only log of every launch, one JSON entry per line under the app dir
+pub struct History;
+
+impl History{
+
+    pub fn history_path() -> Result<PathBuf, anyhow::Error>{
+        let app_dir = match VatConfig::get_app_dir(){
+            Some(app_dir) => app_dir,
+            None => return Err(anyhow!("App directory not found")),
+        };
+        if !app_dir.exists(){
+            fs::create_dir_all(&app_dir)?;
+        }
+        Ok(app_dir.join(HISTORY_FILE_NAME))
+    }
+
+
+    /// Every readable entry, oldest first
+    pub fn read() -> Result<Vec<HistoryEntry>, anyhow::Error>{
+        let history_path = Self::history_path()?;
+        if !history_path.exists(){
+            return Ok(vec![]);
+        }
+        let file = fs::File::open(history_path)?;
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines(){
+            let line = line?;
+            if line.trim().is_empty(){
+                continue;
+            }
+            // a partially written line should not hide the rest of the history
+            if let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line){
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+
+    pub fn get(id: u64) -> Result<HistoryEntry, anyhow::Error>{
+        match Self::read()?.into_iter().find(|entry| entry.id == id){
+            Some(entry) => Ok(entry),
+            None => Err(anyhow!("History entry {} not found", id)),
+        }
+    }
+
+
+    pub fn append(entry: &HistoryEntry) -> Result<(), anyhow::Error>{
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::history_path()?)?;
+        let line = format!("{}\n", serde_json::to_string(entry)?);
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+
+    /// Record a launch, failing to write the history only prints a warning.
+    /// The history is locked from reading the last id to appending, two launches finishing together get different ids.
+    pub fn record(context: &ResolvedContext, launch: &Launch, pid: Option<u32>, exit_status: Option<i32>, detached: bool){
+        let lock = Self::history_path().and_then(|history_path| FileLock::exclusive(&history_path));
+        let result = lock.and_then(|_lock| {
+            let entries = Self::read()?;
+            let id = entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1;
+            let entry = HistoryEntry{
+                id,
+                timestamp: Utc::now(),
+                user: Self::user(),
+                host: Self::host(),
+                cwd: std::env::current_dir().unwrap_or_default(),
+                program: launch.program.clone(),
+                args: launch.args.clone(),
+                pid,
+                exit_status,
+                detached,
+                context: context.clone(),
+            };
+            Self::append(&entry)
+        });
+        if let Err(e) = result{
+            eprintln!("{}", format!("Failed to record the launch in the history: {}", e).yellow());
+        }
+    }
+
+
+    pub fn user() -> String{
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string())
+    }
+
+
+    pub fn host() -> String{
+        if let Ok(host) = std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")){
+            return host;
+        }
+        match fs::read_to_string("/etc/hostname"){
+            Ok(host) if !host.trim().is_empty() => host.trim().to_string(),
+            _ => "unknown".to_string(),
+        }
+    }
+
+
+    pub fn pretty_print(entry: &HistoryEntry){
+        let status = match (entry.detached, entry.exit_status){
+            (true, _) => "detached".yellow(),
+            (false, Some(0)) => "exit 0".green(),
+            (false, Some(code)) => format!("exit {}", code).red(),
+            (false, None) => "unknown".bright_black(),
+        };
+        let timestamp = entry.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string();
+        println!("{} {} {}@{} {} {} {}",
+            format!("#{}", entry.id).green(),
+            timestamp.bright_black(),
+            entry.user,
+            entry.host,
+            format!("{} {}", entry.program, entry.args.join(" ")).trim_end().cyan(),
+            status,
+            format!("pid {}", entry.pid.map(|pid| pid.to_string()).unwrap_or_default()).bright_black(),
+        );
+        println!("   {}", entry.packages().join(" ").bright_black());
+    }
+}
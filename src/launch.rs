@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use anyhow::anyhow;
+use chrono::Local;
+use serde::Serialize;
+use crate::config::VatConfig;
+use crate::output;
+
+
+/// Exit code of a finished process, `128 + signal` when it was killed by a signal on unix
+pub fn exit_code(status: &ExitStatus) -> i32{
+    if let Some(code) = status.code(){
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal(){
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+
+/// Ignores SIGINT and SIGQUIT in vat while a foreground child runs, until dropped.
+///
+/// ctrl-c and ctrl-\ reach every process of the foreground group: the child decides what to do
+/// with them and vat stays alive to record its exit code and run the post hooks.
+/// Created after the child is spawned so the child keeps the default handlers.
+pub struct IgnoreInterrupts{
+    #[cfg(unix)]
+    previous: [libc::sighandler_t; 2],
+}
+
+impl IgnoreInterrupts{
+    pub fn start() -> Self{
+        #[cfg(unix)]
+        unsafe{
+            Self{previous: [libc::signal(libc::SIGINT, libc::SIG_IGN), libc::signal(libc::SIGQUIT, libc::SIG_IGN)]}
+        }
+        #[cfg(not(unix))]
+        Self{}
+    }
+}
+
+impl Drop for IgnoreInterrupts{
+    fn drop(&mut self){
+        #[cfg(unix)]
+        unsafe{
+            libc::signal(libc::SIGINT, self.previous[0]);
+            libc::signal(libc::SIGQUIT, self.previous[1]);
+        }
+    }
+}
+
+
+/// A program to start inside a resolved environment
+#[derive(Debug, Clone)]
+pub struct Launch{
+    pub program: String,
+    pub args: Vec<String>,
+    pub environment_variables: BTreeMap<String, String>,
+    /// removed from the environment inherited from vat
+    pub unset_variables: Vec<String>,
+    /// start from an empty environment instead of the one of vat
+    pub clean: bool,
+    pub current_dir: Option<PathBuf>,
+}
+
+
+/// Outcome of launching a program, printed by `--json`
+#[derive(Serialize, Debug, Clone)]
+pub struct LaunchReport{
+    pub program: String,
+    pub args: Vec<String>,
+    pub pid: u32,
+    /// `None` when the program was detached
+    pub exit_code: Option<i32>,
+    pub detached: bool,
+    /// output of a detached program
+    pub log_file: Option<PathBuf>,
+}
+
+
+/// A process started with [`Launch::run`] that has exited
+#[derive(Debug, Clone)]
+pub struct FinishedProcess{
+    pub pid: u32,
+    pub exit_code: i32,
+}
+
+
+/// A process started with [`Launch::spawn_detached`]
+#[derive(Debug, Clone)]
+pub struct DetachedProcess{
+    pub pid: u32,
+    pub log_file: PathBuf,
+}
+
+
+impl Launch{
+
+    pub fn new(program: &str, args: Vec<String>, environment_variables: BTreeMap<String, String>) -> Self{
+        Self{program: program.to_string(), args, environment_variables, unset_variables: vec![], clean: false, current_dir: None}
+    }
+
+
+    fn command(&self) -> std::process::Command{
+        let mut command = std::process::Command::new(&self.program);
+        command.args(&self.args);
+        if self.clean{
+            command.env_clear();
+        }
+        for variable in &self.unset_variables{
+            command.env_remove(variable);
+        }
+        command.envs(&self.environment_variables);
+        if let Some(current_dir) = &self.current_dir{
+            command.current_dir(current_dir);
+        }
+        command
+    }
+
+
+    /// Run in the foreground, inheriting stdin, stdout and stderr, and wait for the process to exit.
+    /// With `--json` the stdout of the process goes to stderr, stdout is left to the JSON report.
+    pub fn run(&self) -> Result<FinishedProcess, anyhow::Error>{
+        let mut command = self.command();
+        if output::is_json(){
+            command.stdout(std::io::stderr());
+        }
+        let mut child = match command.spawn(){
+            Ok(child) => child,
+            Err(e) => return Err(anyhow!("{}: {}", self.program, e)),
+        };
+        let _ignore_interrupts = IgnoreInterrupts::start();
+        let status = child.wait()?;
+        Ok(FinishedProcess{pid: child.id(), exit_code: exit_code(&status)})
+    }
+
+
+    /// Start the program so it outlives the vat process.
+    ///
+    /// On unix the process gets its own session with `setsid`, on windows it is
+    /// detached from the console. stdin is closed and stdout/stderr are written
+    /// to a log file under the `logs` directory of the app dir.
+    pub fn spawn_detached(&self) -> Result<DetachedProcess, anyhow::Error>{
+        let log_file = Self::log_file_path(&self.program)?;
+        let log = fs::File::create(&log_file)?;
+
+        let mut command = self.command();
+        command.stdin(Stdio::null());
+        command.stdout(log.try_clone()?);
+        command.stderr(log);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            unsafe{
+                command.pre_exec(|| {
+                    if libc::setsid() == -1{
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const DETACHED_PROCESS: u32 = 0x00000008;
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+            command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+        }
+
+        match command.spawn(){
+            // the child is not waited on, it is reparented once vat exits
+            Ok(child) => Ok(DetachedProcess{pid: child.id(), log_file}),
+            Err(e) => Err(anyhow!("{}: {}", self.program, e)),
+        }
+    }
+
+
+    /// `<app dir>/logs/<program>-<timestamp>.log`
+    pub fn log_file_path(program: &str) -> Result<PathBuf, anyhow::Error>{
+        let app_dir = match VatConfig::get_app_dir(){
+            Some(app_dir) => app_dir,
+            None => return Err(anyhow!("App directory not found")),
+        };
+        let logs_dir = app_dir.join("logs");
+        if !logs_dir.exists(){
+            fs::create_dir_all(&logs_dir)?;
+        }
+
+        let program_name = PathBuf::from(program)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "command".to_string());
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S%.3f");
+        Ok(logs_dir.join(format!("{}-{}-{}.log", program_name, timestamp, std::process::id())))
+    }
+}
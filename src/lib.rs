@@ -5,4 +5,18 @@ pub mod registry;
 pub mod stack;
 pub mod burnin;
 pub mod vat_repository;
+pub mod solver;
+pub mod lockfile;
+pub mod context;
+pub mod shell;
+pub mod output;
+pub mod platform;
+pub mod expand;
+pub mod environment;
+pub mod diff;
+pub mod launch;
+pub mod history;
+pub mod remote;
+pub mod atomic;
+pub mod lock;
 
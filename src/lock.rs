@@ -0,0 +1,192 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::ErrorKind;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+use crate::config::VatConfig;
+use crate::history::History;
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// Process holding an exclusive lock, written in the lock file while it is held
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockHolder{
+    pub pid: u32,
+    pub host: String,
+    pub user: String,
+    pub since: DateTime<Utc>,
+}
+
+impl LockHolder{
+    fn current() -> Self{
+        Self{pid: std::process::id(), host: History::host(), user: History::user(), since: Utc::now()}
+    }
+}
+
+impl std::fmt::Display for LockHolder{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        write!(f, "{} on {} (pid {}) since {}", self.user, self.host, self.pid, self.since.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+
+/// OS advisory lock on `<file>.lock`, released when dropped.
+///
+/// Readers take a shared lock, a load-modify-save cycle takes an exclusive one so two processes
+/// publishing to the same repository don't lose each other's changes. Waiting for a lock gives up
+/// after the `lock_timeout` of the config.
+///
+/// The lock file is never removed, a new file would not be locked by the processes waiting on the old one.
+pub struct FileLock{
+    /// `None` when a reader cannot create the lock file, e.g. a read-only repository
+    file: Option<File>,
+    exclusive: bool,
+}
+
+impl FileLock{
+
+    pub fn lock_path(path: &Path) -> PathBuf{
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".lock");
+        path.with_file_name(file_name)
+    }
+
+
+    /// Shared lock on `path`, any number of readers can hold it together
+    pub fn shared(path: &Path) -> Result<Self, anyhow::Error>{
+        Self::acquire(path, false, None)
+    }
+
+
+    /// Exclusive lock on `path`, for reading, modifying and saving it without other processes in between
+    pub fn exclusive(path: &Path) -> Result<Self, anyhow::Error>{
+        Self::acquire(path, true, None)
+    }
+
+
+    /// Exclusive lock waiting at most `timeout`, for `vat.config` which holds the `lock_timeout` itself
+    pub fn exclusive_with_timeout(path: &Path, timeout: Duration) -> Result<Self, anyhow::Error>{
+        Self::acquire(path, true, Some(timeout))
+    }
+
+
+    /// Readers only need read access to the lock file, writers write the holder in it
+    fn open(lock_path: &Path, exclusive: bool) -> Result<Option<File>, anyhow::Error>{
+        let open_error = |e: std::io::Error| anyhow!("Failed to open the lock {}: {}", lock_path.display(), e);
+        if exclusive{
+            let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_path).map_err(open_error)?;
+            return Ok(Some(file));
+        }
+        match File::open(lock_path){
+            Ok(file) => return Ok(Some(file)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(open_error(e)),
+        }
+        match OpenOptions::new().write(true).create(true).truncate(false).open(lock_path){
+            Ok(_) => Ok(Some(File::open(lock_path).map_err(open_error)?)),
+            Err(e) if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem) => {
+                // nobody can write next to it either, there is no writer to wait for
+                crate::output::status(format!("Reading without a lock, {} cannot be created: {}", lock_path.display(), e).bright_black());
+                Ok(None)
+            }
+            Err(e) => Err(open_error(e)),
+        }
+    }
+
+
+    fn acquire(path: &Path, exclusive: bool, timeout: Option<Duration>) -> Result<Self, anyhow::Error>{
+        let lock_path = Self::lock_path(path);
+        let file = match Self::open(&lock_path, exclusive)?{
+            Some(file) => file,
+            None => return Ok(Self{file: None, exclusive}),
+        };
+        let timeout = match timeout{
+            Some(timeout) => timeout,
+            None => Duration::from_secs(VatConfig::init()?.get_lock_timeout()),
+        };
+        let started = Instant::now();
+        let mut waiting = false;
+
+        loop{
+            let result = if exclusive{ file.try_lock() }else{ file.try_lock_shared() };
+            match result{
+                Ok(()) => {
+                    let mut lock = Self{file: Some(file), exclusive};
+                    if exclusive{
+                        lock.write_holder()?;
+                    }
+                    return Ok(lock);
+                }
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    return Err(anyhow!("Failed to lock {}: {}", lock_path.display(), e));
+                }
+            }
+
+            // the holder written in the file is only current when the lock is held exclusively,
+            // a writer that crashed leaves its record behind once readers hold the lock
+            let held_exclusively = !exclusive || match file.try_lock_shared(){
+                Ok(()) => {
+                    file.unlock()?;
+                    false
+                }
+                Err(_) => true,
+            };
+            let holder = if held_exclusively{ Self::read_holder(&lock_path) }else{ None };
+
+            if started.elapsed() >= timeout{
+                let held_by = match holder{
+                    Some(holder) => format!("held by {}", holder),
+                    None if held_exclusively => "held by another process".to_string(),
+                    None => "held by readers".to_string(),
+                };
+                return Err(anyhow!("Timed out after {}s waiting for {}, {}", timeout.as_secs(), lock_path.display(), held_by));
+            }
+            if !waiting{
+                waiting = true;
+                crate::output::status(format!("Waiting for {}", lock_path.display()).bright_black());
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+
+    fn write_holder(&mut self) -> Result<(), anyhow::Error>{
+        let file = match self.file.as_mut(){
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let holder = serde_json::to_string(&LockHolder::current())?;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(holder.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+
+    fn read_holder(lock_path: &Path) -> Option<LockHolder>{
+        let mut contents = String::new();
+        File::open(lock_path).ok()?.read_to_string(&mut contents).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+}
+
+impl Drop for FileLock{
+    fn drop(&mut self){
+        let file = match &self.file{
+            Some(file) => file,
+            None => return,
+        };
+        if self.exclusive{
+            // an empty lock file means nobody holds it exclusively
+            let _ = file.set_len(0);
+        }
+        let _ = file.unlock();
+    }
+}
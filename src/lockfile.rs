@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use anyhow::anyhow;
+use serde::{Serialize, Deserialize};
+use crate::package::Package;
+use crate::solver::{PackageRole, Resolution, ResolvedPackage};
+use crate::vat_repository::VatRepos;
+
+pub const VAT_LOCK: &str = "vat.lock";
+const VAT_LOCK_HEADER: &str = "# This file is generated by vat, do not edit it by hand.\n# Run `vat lock` to resolve the environment again.\n\n";
+
+
+/// A fully resolved environment written next to vat.toml,
+/// `vat run --locked` launches exactly these versions without solving again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockFile{
+    #[serde(rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedPackage{
+    pub name: String,
+    pub version: semver::Version,
+    pub path: PathBuf,
+    pub role: PackageRole,
+    pub main_branch: bool,
+    pub env: Option<Vec<String>>,
+    #[serde(default)]
+    pub repository: Option<String>,
+}
+
+
+impl LockFile{
+
+    pub fn from_resolution(resolution: &Resolution) -> Self{
+        let packages = resolution.packages.iter().map(|resolved_package| LockedPackage{
+            name: resolved_package.name.clone(),
+            version: resolved_package.version.clone(),
+            path: resolved_package.path.clone(),
+            role: resolved_package.role.clone(),
+            main_branch: resolved_package.main_branch,
+            env: resolved_package.env.clone(),
+            repository: resolved_package.repository.clone(),
+        }).collect();
+        Self{packages}
+    }
+
+
+    pub fn lock_file_path(package_path: &Path) -> PathBuf{
+        package_path.join(VAT_LOCK)
+    }
+
+
+    pub fn exists(package_path: &Path) -> bool{
+        Self::lock_file_path(package_path).exists()
+    }
+
+
+    pub fn read(package_path: &Path) -> Result<Self, anyhow::Error>{
+        let lock_file_path = Self::lock_file_path(package_path);
+        if !lock_file_path.exists(){
+            return Err(anyhow!("{} not found in {}, run `vat lock` first", VAT_LOCK, package_path.display()));
+        }
+        let lock_str = fs::read_to_string(&lock_file_path)?;
+        let lock_file: LockFile = toml::from_str(&lock_str)?;
+        Ok(lock_file)
+    }
+
+
+    pub fn save(&self, package_path: &Path) -> Result<(), anyhow::Error>{
+        let lock_str = format!("{}{}", VAT_LOCK_HEADER, toml::to_string(self)?);
+        fs::write(Self::lock_file_path(package_path), lock_str)?;
+        Ok(())
+    }
+
+
+    /// Rebuild the resolution from the locked versions.
+    /// Fails if a published version has been removed from the repository since it was locked.
+    pub fn resolve(&self, vat_repos: &VatRepos) -> Result<Resolution, anyhow::Error>{
+        let mut packages = vec![];
+        for locked_package in &self.packages{
+            if !locked_package.main_branch && !vat_repos.version_exists(&locked_package.name, &locked_package.version, locked_package.repository.as_deref()){
+                return Err(anyhow!("Locked version {}/{} is no longer in the repository", locked_package.name, locked_package.version));
+            }
+            if !Package::is_vat_package(&locked_package.path){
+                return Err(anyhow!("Locked package {}/{} not found at {}", locked_package.name, locked_package.version, locked_package.path.display()));
+            }
+
+            let package = Package::read(&locked_package.path)?;
+            packages.push(ResolvedPackage{
+                name: locked_package.name.clone(),
+                version: locked_package.version.clone(),
+                path: locked_package.path.clone(),
+                package,
+                env: locked_package.env.clone(),
+                role: locked_package.role.clone(),
+                main_branch: locked_package.main_branch,
+                repository: locked_package.repository.clone(),
+            });
+        }
+        Ok(Resolution{packages})
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+
+/// Silence the progress messages printed while resolving and launching,
+/// used when stdout is reserved for output meant to be consumed by another program.
+pub fn set_quiet(quiet: bool){
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool{
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print the result of commands as JSON instead of text, progress messages and colors are turned off
+pub fn set_json(json: bool){
+    JSON.store(json, Ordering::Relaxed);
+    if json{
+        set_quiet(true);
+        colored::control::set_override(false);
+    }
+}
+
+pub fn is_json() -> bool{
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Print a progress message unless output is quiet
+pub fn status<T: Display>(message: T){
+    if !is_quiet(){
+        println!("{}", message);
+    }
+}
+
+/// Print a value as pretty JSON on stdout
+pub fn json<T: Serialize>(value: &T) -> Result<(), anyhow::Error>{
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
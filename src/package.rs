@@ -1,15 +1,22 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::path::PathBuf;
 use std::io::Write;
-use color_print::cprintln;
+use color_print::cformat;
 use git2::Repository as GitRepository;
 use colored::*;
 use crate::git::Git;
-use crate::registry::Registry;
 use crate::git::GitTags;
 use crate::stack::Stack;
-use crate::vat_repository::VatRepo;
+use crate::vat_repository::{VatRepo, VatRepos};
+use crate::config::VatConfig;
+use crate::solver::{Resolution, Solver};
+use crate::lockfile::LockFile;
+use crate::context::ResolvedContext;
+use crate::output;
+use crate::launch::LaunchReport;
+use crate::platform;
 
 const VAT_TOML: &str = "vat.toml";
 
@@ -19,21 +26,24 @@ pub struct Package{
     #[serde(rename="package")]
     pub package_info: PackageInfo,
     pub dependencies: Option<Dependencies>,
-    pub environment: Option<HashMap<String, Environtment>>,
-    pub command: Option<HashMap<String, Command>>,
+    /// kept in the order of vat.toml, entries are applied in that order
+    pub environment: Option<IndexMap<String, Environtment>>,
+    pub command: Option<IndexMap<String, Command>>,
+    /// hooks run around every command of the package
+    pub hooks: Option<Hooks>,
     pub examples: Option<Vec<Example>>
 }
 
 impl Default for Package {
     fn default() -> Self {
-        Self { package_info: PackageInfo::from("".to_string()), dependencies: None, command: Some(HashMap::new()), environment: Some(HashMap::new()), examples: None }
+        Self { package_info: PackageInfo::from("".to_string()), dependencies: None, command: Some(IndexMap::new()), environment: Some(IndexMap::new()), hooks: None, examples: None }
     }
 }
 
 impl Package {  
 
     pub fn from_package_info(package_info: PackageInfo) -> Self{
-        Self { package_info, dependencies: None, command: None, environment: None, examples: None }
+        Self { package_info, dependencies: None, command: None, environment: None, hooks: None, examples: None }
     }
 
 
@@ -51,6 +61,7 @@ impl Package {
             dependencies: None,
             command: None,
             environment: None,
+            hooks: None,
             examples: None,
         }
     }
@@ -63,7 +74,7 @@ impl Package {
         if self.environment.is_some() {
             self.environment.as_mut().unwrap().insert(env_name.to_string(), env);
         }else{
-            let mut environemts = HashMap::new();
+            let mut environemts = IndexMap::new();
             environemts.insert(env_name.to_string(), env);
             self.environment = Some(environemts);
         }
@@ -73,7 +84,7 @@ impl Package {
         if self.command.is_some() {
             self.command.as_mut().unwrap().insert(command_name.to_string(), command);
         }else{
-            let mut commands = HashMap::new();
+            let mut commands = IndexMap::new();
             commands.insert(command_name.to_string(), command);
             self.command = Some(commands);
         }
@@ -83,6 +94,13 @@ impl Package {
         &self.package_info.version
     }
 
+    pub fn get_dependencies(&self) -> Vec<String> {
+        match &self.dependencies {
+            Some(dependencies) => dependencies.dependencies.clone(),
+            None => vec![],
+        }
+    }
+
     pub fn get_version_message(&self) -> Option<&str> {
         self.package_info.version_message.as_ref().map(|s| s.as_str())
     }
@@ -99,6 +117,53 @@ impl Package {
         }
     }
 
+    /// Print the package, marking the environments and commands that are not active on this platform
+    pub fn pretty_print(&self){
+        let message = format!("Package: {} - {}", self.get_name(), self.get_version());
+        println!("{}", message.green());
+        if let Some(description) = &self.package_info.description{
+            println!("   {}", description.bright_black());
+        }
+
+        let dependencies = self.get_dependencies();
+        if !dependencies.is_empty(){
+            println!("{}", "Dependencies:".green());
+            for dependency in dependencies{
+                println!("   {}", dependency);
+            }
+        }
+
+        let host = format!("{}/{}", platform::name(), platform::arch());
+
+        if let Some(environments) = &self.environment{
+            println!("{}", format!("Environment ({}):", host).green());
+            // in the order they are applied
+            for (env_name, env) in environments{
+                let action = env.action.as_ref().map(|a| format!("{:?}", a)).unwrap_or_default();
+                let line = format!("{} - {} {} {}", env_name, action, env.variable, env.value);
+                if env.is_active(){
+                    println!("   {} {}", "active  ".bright_cyan(), line);
+                }else{
+                    let filter = [env.platform.clone(), env.arch.clone()].into_iter().flatten().collect::<Vec<String>>().join(" ");
+                    println!("   {} {}", "inactive".bright_black(), format!("{} ({})", line, filter).bright_black());
+                }
+            }
+        }
+
+        if let Some(commands) = &self.command{
+            println!("{}", format!("Commands ({}):", host).green());
+            for (command_name, command) in commands{
+                let line = format!("{} - {}", command_name, command.command);
+                if command.is_active(){
+                    println!("   {} {}", "active  ".bright_cyan(), line);
+                }else{
+                    let filter = [command.platform.clone(), command.arch.clone()].into_iter().flatten().collect::<Vec<String>>().join(" ");
+                    println!("   {} {}", "inactive".bright_black(), format!("{} ({})", line, filter).bright_black());
+                }
+            }
+        }
+    }
+
     
     pub fn get_env(&self, env_name: &str) -> Option<&Environtment>{
         if self.environment.is_some() {
@@ -109,10 +174,31 @@ impl Package {
         }
     }
 
+    /// Environment entries to apply, in order, with their names.
+    /// `envs` selects entries by name in the given order, otherwise every entry is used in the order of vat.toml.
+    /// Entries not available on the current platform are skipped.
+    pub fn get_envs(&self, envs: Option<Vec<String>>) -> Vec<(String, &Environtment)>{
+        let environments = match &self.environment{
+            Some(environments) => environments,
+            None => return vec![],
+        };
+        let env_names = match envs{
+            Some(env_names) => env_names,
+            None => environments.keys().cloned().collect::<Vec<String>>(),
+        };
+        env_names.into_iter()
+            .filter_map(|env_name| {
+                let env = environments.get(&env_name).filter(|env| env.is_active())?;
+                Some((env_name, env))
+            })
+            .collect()
+    }
+
+    /// Command by name, `None` when it is not available on the current platform
     pub fn get_cmd(&self, cmd_name: &str) -> Option<&Command>{
         if self.command.is_some(){
             let cmds = self.command.as_ref().unwrap();
-            cmds.get(cmd_name)
+            cmds.get(cmd_name).filter(|cmd| cmd.is_active())
         }else{
             None
         }
@@ -154,7 +240,7 @@ impl Package {
         let mut toml_file = std::fs::File::create(vat_yaml_path)?;
         toml_file.write_all(toml_string.as_bytes())?;
 
-        cprintln!("      <green>Created</green> vat package, `{}`", &folder_name);
+        output::status(cformat!("      <green>Created</green> vat package, `{}`", &folder_name));
 
         Ok(Self::default(folder_name.to_string()))
 
@@ -235,157 +321,76 @@ impl Package {
         }
     }
 
-    pub fn process_env(&self, environment_variables: &mut HashMap<String, String>, envs: Option<Vec<String>>, root_path: &PathBuf) {
-        if envs.is_some(){
-            for env_name in envs.unwrap(){
-                let env = self.get_env(&env_name);
-                if env.is_some(){
-                    println!("Resolving Environment Variable: {}", env_name.yellow());
-                    let env = env.unwrap();
-                    let existing_env_values = if environment_variables.contains_key(&env.variable){
-                        environment_variables.get(&env.variable).unwrap().clone()
-                    }else{
-                        std::env::var(&env.variable).unwrap_or_default()
-                    };
-                    match env.action{
-                        Some(EnvAction::Prepend) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            let new_env_value = format!("{};{}", new_env_value, existing_env_values);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Prepended {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}",message);
-                        }
-                        Some(EnvAction::Append) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            let new_env_value = format!("{};{}", existing_env_values, new_env_value);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Appended {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}",message);
-                        }
-                        Some(EnvAction::Define) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Defined {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}", message);
-                        }
-                        None => {}  
-                    }
-                }
-            }
-        }else{
-            if self.environment.is_some(){
-                let environmets = self.environment.as_ref().unwrap();
-                for (env_name, env) in environmets {
-                    println!("Resolving Environemnt Variable: {}", env_name.yellow());
-                    let existing_env_values = if environment_variables.contains_key(&env_name.to_string()){
-                        environment_variables.get(&env_name.to_string()).unwrap().clone()
-                    }else{
-                        std::env::var(&env_name.to_string()).unwrap_or_default()
-                    };
-
-                    match env.action{
-                        Some(EnvAction::Prepend) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            let new_env_value = format!("{};{}", new_env_value, existing_env_values);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Prepended {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}",message);
-                        }
-                        Some(EnvAction::Append) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            let new_env_value = format!("{};{}", existing_env_values, new_env_value);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Appended {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}",message);
-                        }
-                        Some(EnvAction::Define) => {
-                            let new_env_value = Self::parse_root_path(&env.value, root_path);
-                            environment_variables.insert(env.variable.clone(), new_env_value.clone());
-                            let message = format!("   Defined {} - {}", env.variable.bright_cyan(), new_env_value.bright_black());
-                            println!("{}", message);
-                        }
-                        None => {}
-                    }
-
-                }
+    /// Clone a package, an existing clone of the same url fetches the new tags instead
+    pub fn clone_package(git_url: &str, package_path: &PathBuf) -> Result<GitRepository, anyhow::Error> {
+        if package_path.exists(){
+            let git_repo = match GitRepository::open(package_path){
+                Ok(git_repo) => git_repo,
+                Err(_) => return Err(anyhow::anyhow!("{} exists and is not a git repository", package_path.display())),
+            };
+            let remote_url = git_repo.find_remote("origin").ok().and_then(|remote| remote.url().map(|url| url.to_string()));
+            if remote_url.as_deref() != Some(git_url){
+                return Err(anyhow::anyhow!("{} is a clone of another repository", package_path.display()));
             }
+            output::status(format!("Fetching {}", git_url).bright_black());
+            git_repo.fetch_tags()?;
+            return Ok(git_repo);
         }
 
+        output::status(format!("Cloning {}", git_url).bright_black());
+        match GitRepository::clone(git_url, package_path){
+            Ok(git_repo) => Ok(git_repo),
+            Err(e) => Err(anyhow::anyhow!("Failed to clone package {}: {}", git_url, e.message())),
+        }
     }
 
 
-    pub fn run_command(&self, command_name: &str, _root_path: &PathBuf, current_dir: Option<PathBuf>) -> Result<(), anyhow::Error> {
-        
-        if self.command.is_some() {
-            let commands = self.command.as_ref().unwrap();
-            if commands.contains_key(command_name) {
-                let command = commands.get(command_name);
-                if command.is_some() {
-                    let script = command.unwrap().command.clone();
-                    println!("Running Command script: {}", &script);
-
-                    // println!("Current Environment Variables:");
-                    // for (key, value) in std::env::vars() {
-                    //     println!("{}: {}", key, value);
-                    // }
-
-
-                    let mut command = std::process::Command::new(&script);
-                    command.env("NODURO", "PINKMAN");
-                    // command.command_load_env(&self, command_name, root_path);
-                    if current_dir.is_some(){
-                        command.current_dir(current_dir.unwrap());
-                    }
-
-                    let _evn = command.get_envs();
-                    // dbg!(&evn);
-
-
-
-
-                    // command.env("HOUDINI_USER_PREF_DIR", "C:\\Users\\Deepak\\Documents\\houdini20.5");
-                    // command.args(&["/C", "start", "cmd", "/K", &script]);
-                    // command.args(&[&script]);
-                    command.stdout(std::process::Stdio::null());
-                    command.stderr(std::process::Stdio::null());
-                    command.stdin(std::process::Stdio::null());
+    /// Clone a package from its git url into the packages directory and publish a tag of it
+    /// to the publish repository, the latest version tag when `version` is not given.
+    /// A version already in the repository is left as is.
+    pub fn install(git_url: &str, version: Option<semver::Version>) -> Result<Package, anyhow::Error> {
+        let packages_path = match VatConfig::init()?.get_packages_path(){
+            Some(packages_path) => packages_path,
+            None => return Err(anyhow::anyhow!("Packages path not found")),
+        };
+        let clone_name = git_url.trim_end_matches('/').trim_end_matches(".git");
+        let clone_name = match clone_name.rsplit(['/', '\\', ':']).next(){
+            Some(clone_name) if !clone_name.is_empty() => clone_name.to_string(),
+            _ => return Err(anyhow::anyhow!("Invalid git url {}", git_url)),
+        };
+        let package_path = packages_path.join(clone_name);
+        let git_repo = Self::clone_package(git_url, &package_path)?;
+
+        let version = match version{
+            Some(version) => version,
+            None => match GitTags::from_versions(git_repo.get_tags()?).get_latest(){
+                Some(version) => version,
+                None => return Err(anyhow::anyhow!("{} has no version tag", git_url)),
+            },
+        };
+        git_repo.checkout_tag(&version.to_string())?;
 
-                    let _child = command.spawn();
+        let package = Package::read(&package_path)?;
+        if package.get_version() != &version{
+            return Err(anyhow::anyhow!("Tag {} of {} holds version {} in vat.toml", version, git_url, package.get_version()));
+        }
 
-                }
-            }
+        let mut vat_repo = VatRepo::init()?;
+        if vat_repo.version_exists(package.get_name(), &version){
+            output::status(format!("{}: Version {} already installed", package.get_name(), version).yellow());
+            return Ok(package);
         }
-        Ok(())
-    }
-
-    pub fn clone_package(git_url: &str, package_path: &PathBuf) -> Result<Package, anyhow::Error> {
-        // clone with progress
-        let clone = std::process::Command::new("git").args(&["clone", git_url, &package_path.to_str().unwrap()]).output().unwrap();
-        if !clone.status.success() {
-            return Err(anyhow::anyhow!("Failed to clone package: {}", git_url));
-        }
-        let result_read_package = Package::read(&package_path);
-        match result_read_package {
-            Ok(package) => {
-                // register the package
-                let registry = Registry::init();
-                match registry {
-                    Ok(mut registry) => {
-                        let result = registry.add_package(package.clone(), package_path.clone());
-                        match result {
-                            Ok(_) => Ok(package),
-                            Err(e) => Err(e)
-                        }
-                    }
-                    Err(e) => {
-                        Err(e)
-                    }
-                }
-            }
-            Err(e) => {
-                Err(e)
+        vat_repo.publish_package(&package, &package_path, &format!("Installed from {}", git_url))?;
+
+        // the url the package was installed from, when vat.toml does not give one
+        let _lock = vat_repo.lock()?;
+        if let Some(repo_package) = vat_repo.packages.get_mut(package.get_name()){
+            if repo_package.git_url.is_none(){
+                repo_package.git_url = Some(git_url.to_string());
+                vat_repo.save()?;
             }
         }
+        Ok(package)
     }
 
     pub fn get_package_git_tags(package_path: &PathBuf) -> Option<Vec<String>> {
@@ -426,55 +431,76 @@ impl Package {
     }
 
 
-    // package_name is not just package name, it can take package name and version and env
-    // vat run <subcommand> --package <package_name>/<version>[env1,evn2] --append <package_name>/<version>[env1,evn2]
-    // var run <subcommand> will check for current directory for vat.toml file
-    pub fn resolve_package(package_name: Option<String>, check_current_dir: bool) -> Result<PackageResolver, anyhow::Error>{
-        if package_name.is_some(){
-            let package_resolver = PackageResolver::parse_package_string(&package_name.unwrap());
-
-
-            let vat_repo = VatRepo::init()?;
-            let package = vat_repo.get_package(&package_resolver.unwrap());
+    // var run <subcommand> will check for current directory for vat.toml file,
+    // requested packages like <package_name>/<version>[env1,evn2] are resolved by the solver
+    pub fn resolve_package(check_current_dir: bool) -> Result<PackageResolver, anyhow::Error>{
+        if check_current_dir {
+            let current_dir = std::env::current_dir();
+            if current_dir.is_ok() {
+                let current_dir = current_dir.unwrap();
+                let package = Package::read(&current_dir);
+                match package {
+                    Ok(package) => {
 
-            match package {
-                Ok(package_resolver) => {
-                    return Ok(package_resolver);
-                }
-                Err(e) => {
-                    return Err(e);
+                        let package_resolver = PackageResolver::from_package(package,
+                            current_dir,
+                            PackageFrom::Main,
+                            None);
+                        return Ok(package_resolver);
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
                 }
             }
         }else{
-            if check_current_dir {
-                let current_dir = std::env::current_dir();
-                if current_dir.is_ok() {
-                    let current_dir = current_dir.unwrap();
-                    let package = Package::read(&current_dir);
-                    match package {
-                        Ok(package) => {
-
-                            let package_resolver = PackageResolver::from_package(package,
-                                current_dir,
-                                PackageFrom::Main,
-                                None);
-                            return Ok(package_resolver);
-                        }
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    }
+            return Err(anyhow::anyhow!("Either package name should be provided or the current directory should be a vat package"));
+        }
+
+        Err(anyhow::anyhow!("Failed to resolve package"))
+    }
+
+
+    /// Resolve the main package, its dependencies and the append packages into
+    /// a single consistent version per package name.
+    /// Without a package name the vat package in the current directory is the main package.
+    pub fn resolve(package: Option<String>, append: Option<Vec<String>>) -> Result<Resolution, anyhow::Error>{
+        let main_resolver = match package{
+            Some(package) => {
+                match PackageResolver::parse_package_string(&package){
+                    Some(package_resolver) => package_resolver,
+                    None => return Err(anyhow::anyhow!("Invalid package request `{}`", package)),
                 }
-            }else{
-                return Err(anyhow::anyhow!("Either package name should be provided or the current directory should be a vat package"));
+            }
+            None => Package::resolve_package(true)?,
+        };
+
+        let vat_repos = VatRepos::init()?;
+
+        // an append package that is not found fails in the solver, it is never left out of the resolution
+        let mut append_resolvers: Vec<PackageResolver> = vec![];
+        for append_package in append.unwrap_or_default(){
+            match PackageResolver::parse_package_string(&append_package){
+                Some(package_resolver) => append_resolvers.push(package_resolver),
+                None => return Err(anyhow::anyhow!("Invalid package request `{}`", append_package)),
             }
         }
 
-        Err(anyhow::anyhow!("Failed to resolve package"))
+        let mut solver = Solver::new(&vat_repos);
+        solver.solve(&main_resolver, &append_resolvers)
     }
 
 
-    pub fn run_stack(stack: Stack, current_dir: Option<PathBuf>) -> Result<(), anyhow::Error>{
+    /// Resolution from the vat.lock of the package in the current directory
+    pub fn resolve_locked() -> Result<Resolution, anyhow::Error>{
+        let current_dir = std::env::current_dir()?;
+        let lock_file = LockFile::read(&current_dir)?;
+        let vat_repos = VatRepos::init()?;
+        lock_file.resolve(&vat_repos)
+    }
+
+
+    pub fn run_stack(stack: Stack, current_dir: Option<PathBuf>) -> Result<i32, anyhow::Error>{
 
         let package_name = format!("{}/{}", stack.package_name.unwrap(), stack.package_version.unwrap());
         let mut append_packages: Vec<String> = vec![];
@@ -492,124 +518,60 @@ impl Package {
             }
         }
 
-        dbg!(&package_name);
-        dbg!(&append_packages);
+        let launch_report = Self::run(&stack.command.unwrap(), Some(package_name), Some(append_packages), vec![], true, false, false)?;
 
-        Self::run(&stack.command.unwrap(), Some(package_name), Some(append_packages), true)?;
-        
-        Ok(())
+        Ok(launch_report.exit_code.unwrap_or(0))
     }
 
 
 
-    pub fn run(command: &str, package:Option<String>, append: Option<Vec<String>>, detach: bool) -> Result<(), anyhow::Error>{
+    /// Resolve the package request and apply the environment of every resolved package.
+    /// `command` selects the environments of the main package and the program to launch.
+    /// A `clean` context starts from the base environment of the config instead of the current one.
+    pub fn resolve_context(command: Option<&str>, package:Option<String>, append: Option<Vec<String>>, locked: bool, clean: bool) -> Result<ResolvedContext, anyhow::Error>{
 
-        let package = match package {
-            Some(package) => {
-                Package::resolve_package(Some(package), true)
-            }
-            None => {
-                Package::resolve_package(None, true)
-            }
+        let resolution = if locked{
+            Package::resolve_locked()
+        }else{
+            Package::resolve(package, append)
         };
 
-        if package.is_err(){
-            let message = format!("{}", package.err().unwrap()); 
-            return Err(anyhow::anyhow!(message.red()));
-        }
+        let resolution = match resolution{
+            Ok(resolution) => resolution,
+            Err(e) => {
+                let message = format!("{}", e);
+                return Err(anyhow::anyhow!(message.red()));
+            }
+        };
 
-        let package_resolver = package.unwrap();
-        let package_root_path = package_resolver.package_path.unwrap();
-        if package_resolver.package.is_none(){
-            let message = format!("Failed to resolve the main package {}", package_resolver.package_name);
-            return Err(anyhow::anyhow!(message.red()));
-        }
-        let package = package_resolver.package.unwrap();
+        let main_package = match resolution.main(){
+            Some(main_package) => main_package,
+            None => return Err(anyhow::anyhow!("Failed to resolve the main package".red())),
+        };
 
         // Message
-        let packge_version = package.package_info.version.to_string().clone();
-        let message = format!("Package : {} - Version: {}", package_resolver.package_name, packge_version); println!("{}", message.green());
-        let message = format!("Package Path : {}", package_root_path.to_str().unwrap()); println!("{}", message.green());
-
-
-        let mut environment_variables: HashMap<String, String> = HashMap::new();
-        let cmd = package.get_cmd(command);
-        if cmd.is_none(){
-            let message = format!("Command {} not found in package {}", command, package_resolver.package_name); 
-            return Err(anyhow::anyhow!(message.red()));
-        }
-
-        let cmd = cmd.unwrap();
-        if cmd.env.is_some(){
-            package.process_env(&mut environment_variables, cmd.env.clone(), &package_root_path);
-        }else{
-            package.process_env(&mut environment_variables, None, &package_root_path);
-        }
-        
-
-        let mut append_packages: Vec<PackageResolver> = vec![];
-        if append.is_some(){
-            for append_package in append.unwrap() {
-                let package_resolver = Package::resolve_package(Some(append_package), true);
+        let message = format!("Package : {} - Version: {}", main_package.name, main_package.version); output::status(message.green());
+        let message = format!("Package Path : {}", main_package.path.to_str().unwrap()); output::status(message.green());
 
-            match package_resolver {
-                Ok(package_resolver) => {
-                    append_packages.push(package_resolver);
-                }
-                Err(e) => {
-                    let message = format!("Failed to resolve package: {}", e);
-                    println!("{}", message.yellow());
-                    }
-                }
+        match ResolvedContext::from_resolution(&resolution, command, clean){
+            Ok(context) => Ok(context),
+            Err(e) => {
+                let message = format!("{}", e);
+                Err(anyhow::anyhow!(message.red()))
             }
         }
+    }
 
 
-        // first go through append packages
-        for append_package in append_packages {
-            if append_package.package.is_some(){
-                let package_root_path = append_package.package_path.unwrap();
-                let package = append_package.package.unwrap();
-                if append_package.env.is_some(){
-                    package.process_env(&mut environment_variables, append_package.env.clone(), &package_root_path);
-                }else{
-                    package.process_env(&mut environment_variables, None, &package_root_path);
-                }
-            }
-        }
+    /// Run a command of the main package, `args` are passed after the default arguments of the command.
+    pub fn run(command: &str, package:Option<String>, append: Option<Vec<String>>, args: Vec<String>, detach: bool, locked: bool, clean: bool) -> Result<LaunchReport, anyhow::Error>{
 
+        let context = Package::resolve_context(Some(command), package, append, locked, clean)?;
 
         // run the command from main package
-        let mut command_std = std::process::Command::new(&cmd.command);
-        command_std.envs(environment_variables);
-        if !detach{
-            let message = format!("Running Command: {}", cmd.command);
-            println!("{}", message.green());
-            command_std.output().unwrap();  
-        }else{
-            let message = format!("Detaching the process");
-            println!("{}", message.yellow());
-            let message = format!("Running Command: {}", cmd.command);
-            println!("{}", message.green());
-            let child = command_std.spawn();
-            match child{
-                Ok(mut child) => {
-                    let _ = child.wait();
-                }
-                Err(e) => {
-                    let message = format!("{}: {}", cmd.command, e.to_string());
-                    return Err(anyhow::anyhow!(message.red()));
-                }
-            }
-            // if child.is_ok(){
-            //     let _ = child.unwrap().wait();
-            // }else{
-            //     let message = format!("Failed to run command: {}", cmd.command);
-            //     return Err(anyhow::anyhow!(message.red()));
-            // }
-        }
+        let launch_report = context.run(None, &args, detach)?;
 
-        Ok(())
+        Ok(launch_report)
     }
 
 
@@ -673,29 +635,80 @@ pub struct Example {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Command {
     pub command: String,
-    pub env: Option<Vec<String>>
+    pub env: Option<Vec<String>>,
+    /// arguments always passed to the command, before the ones given on the command line
+    pub args: Option<Vec<String>>,
+    /// `false` starts the command from the clean base environment instead of the current one
+    pub inherit_env: Option<bool>,
+    /// run inside the resolved environment before the command
+    pub pre: Option<Vec<Hook>>,
+    /// run inside the resolved environment after the command exits, with `VAT_EXIT_CODE` set
+    pub post: Option<Vec<Hook>>,
+    /// only available on these platforms, comma separated, e.g. `linux,macos`
+    pub platform: Option<String>,
+    /// only available on these architectures, comma separated, e.g. `x86_64`
+    pub arch: Option<String>,
 }
 
 impl Command{
     pub fn from(command: String, env: Option<Vec<String>>) -> Self {
-        Self { command, env }
+        Self { command, env, args: None, inherit_env: None, pre: None, post: None, platform: None, arch: None }
     }
+
+    pub fn is_active(&self) -> bool {
+        platform::matches(self.platform.as_deref(), self.arch.as_deref())
+    }
+}
+
+/// A program run before or after a command, values can use the environment placeholders
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+    pub command: String,
+    pub args: Option<Vec<String>>,
 }
 
+
+/// Hooks run around every command of a package
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hooks {
+    pub pre: Option<Vec<Hook>>,
+    pub post: Option<Vec<Hook>>,
+}
+
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Environtment {
     pub variable: String,
+    /// not used by `Unset` and `Dedupe`
+    #[serde(default)]
     pub value: String,
     pub action: Option<EnvAction>,
+    /// separator used to prepend/append, defaults to the platform path separator
+    pub separator: Option<String>,
+    /// only applied on these platforms, comma separated, e.g. `linux,macos`
+    pub platform: Option<String>,
+    /// only applied on these architectures, comma separated, e.g. `x86_64`
+    pub arch: Option<String>,
 }
 
 impl Environtment {
     pub fn new() -> Self {
-        Self { variable: "PATH".to_string(), value: "{root}/bin".to_string(), action: Some(EnvAction::Define) }
+        Self { variable: "PATH".to_string(), value: "{root}/bin".to_string(), action: Some(EnvAction::Define), separator: None, platform: None, arch: None }
     }
 
     pub fn from(variable: String, value: String, action: Option<EnvAction>) -> Self {
-        Self { variable, value, action }
+        Self { variable, value, action, separator: None, platform: None, arch: None }
+    }
+
+    pub fn is_active(&self) -> bool {
+        platform::matches(self.platform.as_deref(), self.arch.as_deref())
+    }
+
+    pub fn get_separator(&self) -> &str {
+        match &self.separator {
+            Some(separator) => separator.as_str(),
+            None => platform::path_separator(),
+        }
     }
 }
 
@@ -705,6 +718,14 @@ pub enum EnvAction {
     Prepend,
     Append,
     Define,
+    /// define only when the variable is not already set
+    Default,
+    /// remove the entries of the value from a list variable
+    Remove,
+    /// remove duplicated and empty entries from a list variable
+    Dedupe,
+    /// remove the variable from the environment of the launched program
+    Unset,
 }
 
 
@@ -729,17 +750,19 @@ pub struct PackageResolver{
     pub package_path: Option<PathBuf>,
     pub package: Option<Package>,
     pub env: Option<Vec<String>>,
+    /// only resolve from this repository, `name@repository`
+    pub repository: Option<String>,
 }
 
 
 impl PackageResolver{
 
     pub fn new(package_name: String, from: PackageFrom) -> Self{
-        Self { package_name, from, package_path: None, package: None, env: None }
+        Self { package_name, from, package_path: None, package: None, env: None, repository: None }
     }
 
     pub fn from_package(package: Package, package_path: PathBuf, from: PackageFrom, env: Option<Vec<String>>) -> Self{
-        Self { package_name: package.package_info.name.clone(), from, package_path: Some(package_path), package: Some(package), env }
+        Self { package_name: package.package_info.name.clone(), from, package_path: Some(package_path), package: Some(package), env, repository: None }
     }
 
     pub fn parse_package_string(package_string: &str) -> Option<Self>{
@@ -751,6 +774,7 @@ impl PackageResolver{
             package_path: None,
             package: None,
             env: None,
+            repository: None,
 
         };
 
@@ -770,23 +794,35 @@ impl PackageResolver{
         // dbg!(&env_vars);
 
 
-        let pattern = regex::Regex::new(r"^([a-zA-Z0-9-_]+)(?:/([a-zA-Z0-9.-]+))?$").unwrap();
-
-        pattern.captures(&package_str).map(|caps| {
-            package_resolver.package_name = caps.get(1).unwrap().as_str().to_string();
-            package_resolver.from = caps.get(2)
-                .map_or(PackageFrom::Latest, |m| match m.as_str() {
-
-                    "latest" => PackageFrom::Latest,
-
-                    s => match semver::Version::parse(s) {
-                        Ok(version) => PackageFrom::Version(version),
-                        Err(_) => PackageFrom::Main
+        // package name, optionally `@repository`, followed by either `/<version|latest|main|requirement>`
+        // or directly by a requirement like `>=20.5,<21`, `^2024` or `~15.1`
+        let pattern = regex::Regex::new(r"^([a-zA-Z0-9_-]+)(?:@([a-zA-Z0-9_-]+))?\s*(?:/([a-zA-Z0-9.+*,<>=^~ -]+)|([<>=^~*][a-zA-Z0-9.+*,<>=^~ -]*))?$").unwrap();
+
+        let caps = pattern.captures(package_str.trim())?;
+        package_resolver.package_name = caps.get(1).unwrap().as_str().to_string();
+        package_resolver.repository = caps.get(2).map(|m| m.as_str().to_string());
+        package_resolver.from = if let Some(m) = caps.get(3) {
+            match m.as_str().trim() {
+                "latest" => PackageFrom::Latest,
+                "main" => PackageFrom::Main,
+                s => match semver::Version::parse(s) {
+                    Ok(version) => PackageFrom::Version(version),
+                    Err(_) => match semver::VersionReq::parse(s) {
+                        Ok(requirement) => PackageFrom::Requirement(requirement),
+                        Err(_) => return None,
                     }
-                });
-            package_resolver.env = env_vars;
-            package_resolver
-        })
+                }
+            }
+        } else if let Some(m) = caps.get(4) {
+            match semver::VersionReq::parse(m.as_str().trim()) {
+                Ok(requirement) => PackageFrom::Requirement(requirement),
+                Err(_) => return None,
+            }
+        } else {
+            PackageFrom::Latest
+        };
+        package_resolver.env = env_vars;
+        Some(package_resolver)
 
 
     }
@@ -799,8 +835,83 @@ impl PackageResolver{
 pub enum PackageFrom{
     Latest,
     Version(semver::Version),
+    Requirement(semver::VersionReq),
     Main,
 }
 
+impl std::fmt::Display for PackageFrom{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        match self{
+            PackageFrom::Latest => write!(f, "latest"),
+            PackageFrom::Version(version) => write!(f, "={}", version),
+            PackageFrom::Requirement(requirement) => write!(f, "{}", requirement),
+            PackageFrom::Main => write!(f, "main"),
+        }
+    }
+}
 
 
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn parse(package_string: &str) -> PackageResolver{
+        PackageResolver::parse_package_string(package_string).unwrap()
+    }
+
+    fn requirement(package_string: &str) -> semver::VersionReq{
+        match parse(package_string).from{
+            PackageFrom::Requirement(requirement) => requirement,
+            from => panic!("{} parsed as {:?}", package_string, from),
+        }
+    }
+
+    #[test]
+    fn latest_exact_and_main(){
+        assert!(matches!(parse("houdini").from, PackageFrom::Latest));
+        assert!(matches!(parse("houdini/latest").from, PackageFrom::Latest));
+        assert!(matches!(parse("houdini/main").from, PackageFrom::Main));
+        match parse("houdini/20.5.1").from{
+            PackageFrom::Version(version) => assert_eq!(version, semver::Version::new(20, 5, 1)),
+            from => panic!("parsed as {:?}", from),
+        }
+    }
+
+    #[test]
+    fn requirements(){
+        let houdini = requirement("houdini>=20.5,<21");
+        assert!(houdini.matches(&semver::Version::new(20, 5, 3)));
+        assert!(!houdini.matches(&semver::Version::new(21, 0, 0)));
+        assert!(requirement("maya^2024").matches(&semver::Version::new(2024, 2, 0)));
+        assert!(!requirement("maya^2024").matches(&semver::Version::new(2025, 0, 0)));
+        assert!(requirement("nuke~15.1").matches(&semver::Version::new(15, 1, 4)));
+        assert!(!requirement("nuke~15.1").matches(&semver::Version::new(15, 2, 0)));
+        // a partial version after the slash is a requirement, not an exact version
+        assert!(requirement("usd/24.0").matches(&semver::Version::new(24, 0, 2)));
+        assert_eq!(parse("houdini>=20.5,<21").package_name, "houdini");
+    }
+
+    #[test]
+    fn repository_and_environments(){
+        let resolver = parse("ocio@site/1.2.3[OCIO, PATH]");
+        assert_eq!(resolver.package_name, "ocio");
+        assert_eq!(resolver.repository.as_deref(), Some("site"));
+        assert!(matches!(resolver.from, PackageFrom::Version(ref version) if version == &semver::Version::new(1, 2, 3)));
+        assert_eq!(resolver.env, Some(vec!["OCIO".to_string(), "PATH".to_string()]));
+
+        let resolver = parse("usd@show>=24");
+        assert_eq!(resolver.repository.as_deref(), Some("show"));
+        assert!(matches!(resolver.from, PackageFrom::Requirement(_)));
+        assert_eq!(parse("usd").repository, None);
+    }
+
+    #[test]
+    fn invalid_requests(){
+        assert!(PackageResolver::parse_package_string("").is_none());
+        assert!(PackageResolver::parse_package_string("houdini/not a version").is_none());
+        assert!(PackageResolver::parse_package_string("houdini>=banana").is_none());
+        assert!(PackageResolver::parse_package_string("hou dini").is_none());
+        assert!(PackageResolver::parse_package_string("houdini@/20.5.1").is_none());
+        assert!(PackageResolver::parse_package_string("houdini/20.5.1/extra").is_none());
+    }
+}
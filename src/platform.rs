@@ -0,0 +1,45 @@
+/// Name of the current platform, `windows`, `linux`, `macos` or the raw os name for others
+pub fn name() -> &'static str{
+    std::env::consts::OS
+}
+
+/// Separator between the entries of list variables like `PATH` on the current platform
+pub fn path_separator() -> &'static str{
+    if cfg!(target_os = "windows"){
+        ";"
+    }else{
+        ":"
+    }
+}
+
+/// Architecture of the current host, e.g. `x86_64` or `aarch64`
+pub fn arch() -> &'static str{
+    std::env::consts::ARCH
+}
+
+
+fn normalize(value: &str) -> String{
+    let value = value.trim().to_lowercase();
+    match value.as_str(){
+        "win" | "win32" | "win64" => "windows".to_string(),
+        "osx" | "darwin" | "mac" => "macos".to_string(),
+        "amd64" | "x64" => "x86_64".to_string(),
+        "arm64" => "aarch64".to_string(),
+        _ => value,
+    }
+}
+
+
+/// Whether an entry filtered by a comma separated list of platforms and/or
+/// architectures applies to the current host, no filter always applies.
+pub fn matches(platform: Option<&str>, arch: Option<&str>) -> bool{
+    let platform_matches = match platform{
+        Some(platform) => platform.split(',').any(|p| normalize(p) == name()),
+        None => true,
+    };
+    let arch_matches = match arch{
+        Some(arch) => arch.split(',').any(|a| normalize(a) == self::arch()),
+        None => true,
+    };
+    platform_matches && arch_matches
+}
@@ -4,6 +4,8 @@ use serde::{Serialize, Deserialize};
 use crate::package::Package;
 use crate::config::VatConfig;
 use std::fs;
+use crate::atomic;
+use crate::lock::FileLock;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PackageRegistry{
@@ -28,23 +30,24 @@ impl Registry{
     pub fn init() -> Result<Self, anyhow::Error>{
         let registry_path = Self::registry_path();
         if !registry_path.exists(){
-            let registry = Registry::default();
-            let registry_str = serde_json::to_string(&registry);
-            match registry_str{
-                Ok(registry_str) => {
-                    dbg!(&registry_str);
-                    dbg!(&registry_path);
-                    fs::write(&registry_path, registry_str).unwrap();
-                    return Ok(registry);
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!("Failed to write registry: {}", e));
+            let _lock = FileLock::exclusive(&registry_path)?;
+            // another process may have created it while this one waited for the lock
+            if !registry_path.exists(){
+                let registry = Registry::default();
+                let registry_str = serde_json::to_string(&registry);
+                match registry_str{
+                    Ok(registry_str) => {
+                        atomic::write(&registry_path, &registry_str)?;
+                        return Ok(registry);
+                    }
+                    Err(e) => {
+                        return Err(anyhow::anyhow!("Failed to write registry: {}", e));
+                    }
                 }
             }
         }
 
-        let registry_str = fs::read_to_string(&registry_path).unwrap();
-        let mut registry: Registry = serde_json::from_str(&registry_str).unwrap();
+        let mut registry = atomic::read(&registry_path, Self::parse)?;
         let mut sorted_registry = HashMap::new();
         let mut sorted_keys = registry.registry.keys().collect::<Vec<&String>>();
         sorted_keys.sort();
@@ -57,6 +60,23 @@ impl Registry{
     }
 
 
+    fn parse(registry_str: &str) -> Result<Self, anyhow::Error>{
+        Ok(serde_json::from_str::<Registry>(registry_str)?)
+    }
+
+
+    /// Lock the registry for a load-modify-save cycle and reload it,
+    /// it is released when the returned lock is dropped
+    pub fn lock(&mut self) -> Result<FileLock, anyhow::Error>{
+        let registry_path = Self::registry_path();
+        let lock = FileLock::exclusive(&registry_path)?;
+        if registry_path.exists(){
+            self.registry = atomic::read_or_restore(&registry_path, Self::parse)?.registry;
+        }
+        Ok(lock)
+    }
+
+
     // this will also save the package in the defualt pacakge directory
     pub fn append_default_package(&mut self, package: Package) -> Result<(), anyhow::Error>{
 
@@ -76,6 +96,7 @@ impl Registry{
             dbg!(&result);
         }
 
+        let _lock = self.lock()?;
         self.registry.insert(package.package_info.name, package_registry);
         self.save()?;
         Ok(())
@@ -97,6 +118,7 @@ impl Registry{
     }
 
     pub fn add_package(&mut self, package: Package, path: PathBuf) -> Result<(), anyhow::Error>{
+        let _lock = self.lock()?;
         if self.registry.contains_key(&package.package_info.name){
             return Err(anyhow::anyhow!("Package already exists"));
         }
@@ -115,6 +137,7 @@ impl Registry{
     }
 
     pub fn remove_package(&mut self, package_name: &str) -> Result<(), anyhow::Error>{
+        let _lock = self.lock()?;
         if !self.registry.contains_key(package_name){
             return Err(anyhow::anyhow!("Package does not exist"));
         }
@@ -126,7 +149,7 @@ impl Registry{
     pub fn save(&self) -> Result<(), anyhow::Error>{
         let registry_path = Self::registry_path();
         let registry_str = serde_json::to_string(&self).unwrap();
-        fs::write(&registry_path, registry_str).unwrap();
+        atomic::write(&registry_path, &registry_str)?;
         Ok(())
     }   
 
@@ -158,94 +181,3 @@ impl Registry{
         }
     }
 }
-
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct RegistryLock{
-    pub read: bool,
-    pub write: bool,
-}
-
-impl Default for RegistryLock{
-    fn default() -> Self{
-        RegistryLock{read: false, write: false}
-    }
-}
-
-
-impl RegistryLock{
-    pub fn new() -> Self{
-        RegistryLock{read: false, write: false}
-    }
-
-    pub fn init() -> Result<Self, anyhow::Error>{
-        let registry_lock_path = Self::lock_file_path();
-        if !registry_lock_path.exists(){
-            let registry_lock = Self::new();
-            let registry_lock_str = serde_json::to_string(&registry_lock).unwrap();
-            fs::write(&registry_lock_path, registry_lock_str).unwrap();
-            return Ok(registry_lock);
-        }
-        let registry_lock_str = fs::read_to_string(&registry_lock_path).unwrap();
-        let registry_lock: RegistryLock = serde_json::from_str(&registry_lock_str).unwrap();
-        Ok(registry_lock)
-    }   
-
-
-    pub fn is_read_locked(&self) -> bool{
-        self.read
-    }
-
-    pub fn is_write_locked(&self) -> bool{
-        self.write
-    }
-
-    pub fn lock_read(&mut self) -> Result<(), anyhow::Error>{
-        self.read = true;
-        self.save()?;
-        Ok(())
-    }
-
-    pub fn lock_write(&mut self) -> Result<(), anyhow::Error>{
-        self.write = true;
-        self.read = true;
-        self.save()?;
-        Ok(())
-    }
-
-    pub fn unlock_read(&mut self) -> Result<(), anyhow::Error>{
-        self.read = false;
-        self.save()?;
-        Ok(())
-    }
-
-    pub fn unlock_write(&mut self) -> Result<(), anyhow::Error>{
-        self.write = false;
-        self.read = false;
-        self.save()?;
-        Ok(())
-    }
-
-    pub fn lock_file_path() -> PathBuf{
-        let app_dir = VatConfig::get_app_dir().unwrap();
-        let registry_lock_path = app_dir.join("registry.lock");
-        registry_lock_path
-    }
-
-    pub fn save(&self) -> Result<(), anyhow::Error>{
-        let registry_lock_path = Self::lock_file_path();
-        let registry_lock_str = serde_json::to_string(&self).unwrap();
-        fs::write(&registry_lock_path, registry_lock_str).unwrap();
-        Ok(())
-    }
-}
-
-// impl Drop for RegistryLock{
-//     fn drop(&mut self){
-//         self.read = true;
-//         self.write = true;
-//         let registry_lock_path = Self::lock_file_path();
-//         let registry_lock_str = serde_json::to_string(&self).unwrap();
-//         fs::write(&registry_lock_path, registry_lock_str).unwrap();
-//     }
-// }
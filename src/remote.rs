@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::anyhow;
+use git2::{build::CheckoutBuilder, Direction, Repository as GitRepository};
+use serde::Serialize;
+use crate::config::VatConfig;
+use crate::output;
+use crate::lock::FileLock;
+use crate::vat_repository::VatRepo;
+
+/// Name of the git remote a repository is mirrored from
+pub const VAT_REMOTE: &str = "origin";
+
+
+/// Outcome of `vat repo sync` for one repository
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncReport{
+    pub repository: String,
+    pub remote: String,
+    pub branch: String,
+    /// commit the repository was at before the sync, `None` on the first sync
+    pub from: Option<String>,
+    pub to: String,
+    /// `name/version` of the versions that were not in the repository before the sync
+    pub published: Vec<String>,
+}
+
+
+/// A repository directory mirrored from a remote git repository.
+///
+/// The remote holds `vat.repository.toml` and the published versions, committed by the site
+/// releasing the packages. Syncing only fast-forwards, the mirror is never pushed.
+pub struct RepositoryRemote;
+
+impl RepositoryRemote{
+
+    /// Set the remote of the repository, the repository directory becomes a git repository if it is not one
+    pub fn add_remote(vat_repo: &VatRepo, url: &str) -> Result<(), anyhow::Error>{
+        // the first sync replaces the index, only the url of a mirror can be changed once it has packages
+        if !vat_repo.packages.is_empty() && Self::get_remote(vat_repo).is_none(){
+            return Err(anyhow!("Repository {} already has packages, add the remote to an empty repository", vat_repo.name));
+        }
+        let git_repo = match GitRepository::open(&vat_repo.path){
+            Ok(git_repo) => git_repo,
+            Err(_) => GitRepository::init(&vat_repo.path)?,
+        };
+        if git_repo.find_remote(VAT_REMOTE).is_ok(){
+            git_repo.remote_set_url(VAT_REMOTE, url)?;
+        }else{
+            git_repo.remote(VAT_REMOTE, url)?;
+        }
+
+        // lock files and backups of the index are local to each copy of the repository
+        let exclude_path = git_repo.path().join("info").join("exclude");
+        let exclude = fs::read_to_string(&exclude_path).unwrap_or_default();
+        if !exclude.lines().any(|line| line == "*.lock"){
+            fs::create_dir_all(git_repo.path().join("info"))?;
+            fs::write(&exclude_path, format!("{}*.lock\n*.bak\n*.corrupt\n*.tmp.*\n", exclude))?;
+        }
+        Ok(())
+    }
+
+
+    /// Url of the remote of the repository, `None` when it is not mirrored
+    pub fn get_remote(vat_repo: &VatRepo) -> Option<String>{
+        let git_repo = GitRepository::open(&vat_repo.path).ok()?;
+        let remote = git_repo.find_remote(VAT_REMOTE).ok()?;
+        remote.url().map(|url| url.to_string())
+    }
+
+
+    /// Fetch the default branch of the remote and fast-forward the repository to it
+    pub fn sync(vat_repo: &VatRepo) -> Result<SyncReport, anyhow::Error>{
+        let git_repo = match GitRepository::open(&vat_repo.path){
+            Ok(git_repo) => git_repo,
+            Err(_) => return Err(anyhow!("Repository {} has no remote, run `vat repo add-remote` first", vat_repo.name)),
+        };
+        let mut remote = match git_repo.find_remote(VAT_REMOTE){
+            Ok(remote) => remote,
+            Err(_) => return Err(anyhow!("Repository {} has no remote, run `vat repo add-remote` first", vat_repo.name)),
+        };
+        let url = remote.url().unwrap_or_default().to_string();
+
+        output::status(format!("Fetching {} from {}", vat_repo.name, url));
+        remote.connect(Direction::Fetch)?;
+        let default_branch = remote.default_branch()?;
+        remote.disconnect()?;
+        let branch = match default_branch.as_str(){
+            Some(default_branch) => default_branch.trim_start_matches("refs/heads/").to_string(),
+            None => return Err(anyhow!("Remote {} has no default branch", url)),
+        };
+
+        let remote_ref = format!("refs/remotes/{}/{}", VAT_REMOTE, branch);
+        let refspec = format!("+refs/heads/{}:{}", branch, remote_ref);
+        remote.fetch(&[refspec.as_str()], None, None)?;
+        let remote_commit = git_repo.find_reference(&remote_ref)?.peel_to_commit()?;
+
+        let head_commit = match git_repo.head(){
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(_) => None,
+        };
+
+        let mut report = SyncReport{
+            repository: vat_repo.name.clone(),
+            remote: url.clone(),
+            branch: branch.clone(),
+            from: head_commit.as_ref().map(|commit| commit.id().to_string()),
+            to: remote_commit.id().to_string(),
+            published: vec![],
+        };
+
+        let mut checkout = CheckoutBuilder::new();
+        match &head_commit{
+            Some(head_commit) if head_commit.id() == remote_commit.id() => return Ok(report),
+            Some(head_commit) => {
+                if !git_repo.graph_descendant_of(remote_commit.id(), head_commit.id())?{
+                    return Err(anyhow!("Repository {} has commits that are not on {}, it cannot be fast-forwarded", vat_repo.name, url));
+                }
+                // local changes to the mirrored files stop the sync instead of being overwritten
+                checkout.safe();
+            }
+            None => {
+                // first sync, the index written when the repository was opened is replaced
+                if !vat_repo.packages.is_empty(){
+                    return Err(anyhow!("Repository {} already has packages, add the remote to an empty repository", vat_repo.name));
+                }
+                checkout.force();
+            }
+        }
+
+        {
+            // readers don't see the index and the published versions half checked out
+            let _lock = FileLock::exclusive(&vat_repo.path.join("vat.repository.toml"))?;
+            git_repo.checkout_tree(remote_commit.as_object(), Some(&mut checkout))?;
+            git_repo.reference(&format!("refs/heads/{}", branch), remote_commit.id(), true, "vat repo sync")?;
+            git_repo.set_head(&format!("refs/heads/{}", branch))?;
+        }
+
+        let synced_repo = VatRepo::open(&vat_repo.name, &vat_repo.path)?;
+        for (package_name, repo_package) in &synced_repo.packages{
+            for version in repo_package.versions.keys(){
+                if !vat_repo.version_exists(package_name, version){
+                    report.published.push(format!("{}/{}", package_name, version));
+                }
+            }
+        }
+        report.published.sort();
+        Ok(report)
+    }
+
+
+    /// Directory of a repository created by `vat repo add-remote`
+    pub fn default_repository_path(name: &str) -> Result<PathBuf, anyhow::Error>{
+        match VatConfig::get_app_dir(){
+            Some(app_dir) => Ok(app_dir.join("repositories").join(name)),
+            None => Err(anyhow!("App directory not found")),
+        }
+    }
+}
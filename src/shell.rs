@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::fs;
+use anyhow::anyhow;
+use colored::Colorize;
+use crate::context::ResolvedContext;
+use crate::output;
+use crate::launch::{self, IgnoreInterrupts};
+
+
+/// Shells the resolved environment can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell{
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Cmd,
+}
+
+impl FromStr for Shell{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>{
+        match s.to_lowercase().as_str(){
+            "bash" | "sh" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            "cmd" => Ok(Shell::Cmd),
+            _ => Err(anyhow!("Unsupported shell `{}`, expected one of bash, zsh, fish, powershell, cmd", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Shell{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        let name = match self{
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "powershell",
+            Shell::Cmd => "cmd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+impl Shell{
+
+    /// Shell of the current user from `$SHELL`, powershell on windows
+    pub fn detect() -> Self{
+        if cfg!(target_os = "windows"){
+            return Shell::PowerShell;
+        }
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        let shell_name = std::path::Path::new(&shell)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Shell::from_str(&shell_name).unwrap_or(Shell::Bash)
+    }
+
+
+    /// A single line setting the variable, quoted for the shell.
+    ///
+    /// The cmd script is meant to be saved as a batch file and `call`ed: `%` is doubled, which
+    /// only reads as a single `%` in a batch file, an interactive cmd prompt has no escape for it.
+    pub fn export(&self, name: &str, value: &str) -> String{
+        match self{
+            Shell::Bash | Shell::Zsh => format!("export {}={}", name, self.quote(value)),
+            Shell::Fish => format!("set -gx {} {}", name, self.quote(value)),
+            Shell::PowerShell => format!("$env:{} = {}", name, self.quote(value)),
+            Shell::Cmd => format!("set \"{}={}\"", name, value.replace('%', "%%")),
+        }
+    }
+
+
+    /// A value taken literally by the shell
+    pub fn quote(&self, value: &str) -> String{
+        match self{
+            Shell::Bash | Shell::Zsh => format!("'{}'", value.replace('\'', "'\\''")),
+            Shell::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+            Shell::PowerShell => format!("'{}'", value.replace('\'', "''")),
+            Shell::Cmd => format!("\"{}\"", value.replace('%', "%%")),
+        }
+    }
+
+
+    /// A single line removing the variable
+    pub fn unset(&self, name: &str) -> String{
+        match self{
+            Shell::Bash | Shell::Zsh => format!("unset {}", name),
+            Shell::Fish => format!("set -e {}", name),
+            Shell::PowerShell => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name),
+            Shell::Cmd => format!("set \"{}=\"", name),
+        }
+    }
+
+
+    /// Script removing the unset variables then setting every variable, sorted by name so the output is stable
+    pub fn script(&self, environment_variables: &BTreeMap<String, String>, unset_variables: &[String]) -> String{
+        let mut script = String::new();
+        for name in unset_variables{
+            script.push_str(&self.unset(name));
+            script.push('\n');
+        }
+        for (name, value) in environment_variables{
+            script.push_str(&self.export(name, value));
+            script.push('\n');
+        }
+        script
+    }
+
+
+    /// Executable of the shell, `$SHELL` when it is the same kind of shell
+    pub fn executable(&self) -> String{
+        let user_shell = std::env::var("SHELL").unwrap_or_default();
+        match self{
+            Shell::Bash | Shell::Zsh | Shell::Fish => {
+                let is_user_shell = std::path::Path::new(&user_shell)
+                    .file_name()
+                    .map(|name| name.to_string_lossy() == self.to_string())
+                    .unwrap_or(false);
+                if is_user_shell{
+                    user_shell
+                }else{
+                    self.to_string()
+                }
+            }
+            Shell::PowerShell => {
+                if cfg!(target_os = "windows"){
+                    "powershell".to_string()
+                }else{
+                    "pwsh".to_string()
+                }
+            }
+            Shell::Cmd => "cmd".to_string(),
+        }
+    }
+
+
+    /// Start an interactive sub-shell inside the resolved environment and wait for it to exit.
+    ///
+    /// The user's startup files are still read, the resolved variables are exported again
+    /// after them so they cannot be overridden, then the prompt is prefixed with the active packages.
+    /// Returns the exit code of the shell.
+    pub fn spawn(&self, context: &ResolvedContext) -> Result<i32, anyhow::Error>{
+        let mut environment_variables = context.launch_environment()?;
+        environment_variables.extend(context.vat_variables());
+
+        let shell_level = std::env::var("VAT_SHELL_LEVEL").ok()
+            .and_then(|level| level.parse::<u32>().ok())
+            .unwrap_or(0);
+        environment_variables.insert("VAT_SHELL_LEVEL".to_string(), (shell_level + 1).to_string());
+
+        let label = context.prompt_label();
+        let exports = self.script(&environment_variables, &context.unset_variables);
+
+        let mut command = std::process::Command::new(self.executable());
+        if context.clean{
+            command.env_clear();
+        }
+        for variable in &context.unset_variables{
+            command.env_remove(variable);
+        }
+        command.envs(&environment_variables);
+
+        // the rc files are sourced by the shell, the directory is created with a random name,
+        // only accessible to the user and never reused, it is removed when dropped
+        let mut temp_builder = tempfile::Builder::new();
+        temp_builder.prefix("vat-shell-");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            temp_builder.permissions(fs::Permissions::from_mode(0o700));
+        }
+        let temp_dir = temp_builder.tempdir()?;
+
+        match self{
+            Shell::Bash => {
+                let rc_file = temp_dir.path().join("bashrc");
+                let rc = format!("[ -f ~/.bashrc ] && . ~/.bashrc\n{}PS1=\"[{}] $PS1\"\n", exports, label);
+                fs::write(&rc_file, rc)?;
+                command.arg("--rcfile").arg(&rc_file).arg("-i");
+            }
+            Shell::Zsh => {
+                // zsh reads .zshenv then .zshrc from ZDOTDIR, the user's .zshenv is sourced from the
+                // temporary one and can move ZDOTDIR, the user's .zshrc is read from where it points
+                let user_zdotdir = std::env::var("ZDOTDIR").unwrap_or(std::env::var("HOME").unwrap_or_default());
+                let temp_zdotdir = temp_dir.path().to_string_lossy().to_string();
+                let zshenv = format!(
+                    "ZDOTDIR={}\n[ -f \"$ZDOTDIR/.zshenv\" ] && . \"$ZDOTDIR/.zshenv\"\n_vat_zdotdir=\"$ZDOTDIR\"\nZDOTDIR={}\n",
+                    self.quote(&user_zdotdir), self.quote(&temp_zdotdir)
+                );
+                fs::write(temp_dir.path().join(".zshenv"), zshenv)?;
+                let rc = format!("ZDOTDIR=\"$_vat_zdotdir\"\nunset _vat_zdotdir\n[ -f \"$ZDOTDIR/.zshrc\" ] && . \"$ZDOTDIR/.zshrc\"\n{}PROMPT=\"[{}] $PROMPT\"\n", exports, label);
+                fs::write(temp_dir.path().join(".zshrc"), rc)?;
+                command.env("ZDOTDIR", temp_dir.path()).arg("-i");
+            }
+            Shell::Fish => {
+                let init = format!("{}functions -c fish_prompt _vat_fish_prompt\nfunction fish_prompt; echo -n '[{}] '; _vat_fish_prompt; end\n", exports, label);
+                command.arg("--init-command").arg(init);
+            }
+            Shell::PowerShell => {
+                let init = format!("{}function prompt {{ '[{}] PS ' + $(Get-Location) + '> ' }}", exports.replace('\n', "; "), label);
+                command.arg("-NoExit").arg("-Command").arg(init);
+            }
+            Shell::Cmd => {
+                command.arg("/K").arg(format!("prompt [{}] $P$G", label));
+            }
+        }
+
+        let message = format!("Entering {} shell, exit to return to the previous environment", label);
+        output::status(message.green());
+
+        // ctrl-c at the prompt of the sub-shell must not kill vat and orphan the shell
+        let status = command.spawn().and_then(|mut child| {
+            let _ignore_interrupts = IgnoreInterrupts::start();
+            child.wait()
+        });
+        drop(temp_dir);
+
+        match status{
+            Ok(status) => Ok(launch::exit_code(&status)),
+            Err(e) => Err(anyhow!("{}: {}", self.executable(), e)),
+        }
+    }
+}
@@ -0,0 +1,618 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use anyhow::anyhow;
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+use crate::package::{Package, PackageFrom, PackageResolver};
+use crate::vat_repository::VatRepos;
+use crate::output;
+use crate::expand::ExpandContext;
+use crate::environment::Environment;
+
+
+/// A version request on a package and the chain of packages that led to it.
+///
+/// `required_by` is empty for the packages requested on the command line,
+/// otherwise it holds `name/version` of every package from the root down to
+/// the package that declared the dependency.
+#[derive(Debug, Clone)]
+pub struct Requirement{
+    pub package_name: String,
+    pub from: PackageFrom,
+    pub env: Option<Vec<String>>,
+    pub repository: Option<String>,
+    pub required_by: Vec<String>,
+}
+
+impl Requirement{
+    pub fn from_resolver(package_resolver: &PackageResolver, required_by: Vec<String>) -> Self{
+        Self{
+            package_name: package_resolver.package_name.clone(),
+            from: package_resolver.from.clone(),
+            env: package_resolver.env.clone(),
+            repository: package_resolver.repository.clone(),
+            required_by,
+        }
+    }
+
+    pub fn is_satisfied_by(&self, candidate: &Candidate) -> bool{
+        if self.repository.is_some() && candidate.repository != self.repository{
+            return false;
+        }
+        if candidate.main_branch && !candidate.fallback{
+            return matches!(self.from, PackageFrom::Main);
+        }
+        match &self.from{
+            PackageFrom::Latest => true,
+            PackageFrom::Version(version) => &candidate.version == version,
+            PackageFrom::Requirement(requirement) => requirement.matches(&candidate.version),
+            PackageFrom::Main => candidate.main_branch,
+        }
+    }
+}
+
+impl std::fmt::Display for Requirement{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        let package_name = match &self.repository{
+            Some(repository) => format!("{}@{}", self.package_name, repository),
+            None => self.package_name.clone(),
+        };
+        let request = match &self.from{
+            PackageFrom::Latest => package_name,
+            PackageFrom::Version(version) => format!("{}/{}", package_name, version),
+            PackageFrom::Requirement(requirement) => format!("{}{}", package_name, requirement),
+            PackageFrom::Main => format!("{}/main", package_name),
+        };
+        if self.required_by.is_empty(){
+            write!(f, "(request) -> {}", request)
+        }else{
+            write!(f, "{} -> {}", self.required_by.join(" -> "), request)
+        }
+    }
+}
+
+
+/// A version of a package that can be selected by the solver.
+///
+/// The main branch of a linked package only satisfies `name/main`, unless
+/// nothing has been published yet, then it stands in for the published versions
+/// with the version of its vat.toml (`fallback`).
+#[derive(Debug, Clone)]
+pub struct Candidate{
+    pub version: semver::Version,
+    pub path: PathBuf,
+    pub main_branch: bool,
+    pub fallback: bool,
+    /// repository the version is published in, `None` for a package read from disk
+    pub repository: Option<String>,
+}
+
+
+/// Requirements on the same package that no available version satisfies together
+#[derive(Debug, Clone)]
+pub struct Conflict{
+    pub package_name: String,
+    pub requirements: Vec<Requirement>,
+}
+
+impl std::fmt::Display for Conflict{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        if self.requirements.len() == 1{
+            write!(f, "No version of {} satisfies the requirement:", self.package_name)?;
+        }else{
+            write!(f, "Conflicting requirements for {}:", self.package_name)?;
+        }
+        for requirement in &self.requirements{
+            write!(f, "\n   {}", requirement)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PackageRole{
+    Main,
+    Dependency,
+    Append,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ResolvedPackage{
+    pub name: String,
+    pub version: semver::Version,
+    pub path: PathBuf,
+    pub package: Package,
+    pub env: Option<Vec<String>>,
+    pub role: PackageRole,
+    pub main_branch: bool,
+    pub repository: Option<String>,
+}
+
+
+/// Outcome of the solver, one version per package name.
+///
+/// `packages` is in the order the environments are applied: the dependencies
+/// of the main package, the main package, then every append package preceded
+/// by its own dependencies.
+#[derive(Debug, Clone)]
+pub struct Resolution{
+    pub packages: Vec<ResolvedPackage>,
+}
+
+impl Resolution{
+    pub fn main(&self) -> Option<&ResolvedPackage>{
+        self.packages.iter().find(|p| p.role == PackageRole::Main)
+    }
+
+    pub fn get(&self, package_name: &str) -> Option<&ResolvedPackage>{
+        self.packages.iter().find(|p| p.name == package_name)
+    }
+
+    /// Apply the environment of every package in order.
+    /// `main_env` selects the environments of the main package, usually from the command.
+    pub fn process_env(&self, environment: &mut Environment, main_env: Option<Vec<String>>) -> Result<(), anyhow::Error>{
+        let expand_context = ExpandContext::from_resolution(self);
+        for resolved_package in &self.packages{
+            let envs = if resolved_package.role == PackageRole::Main{
+                main_env.clone()
+            }else{
+                resolved_package.env.clone()
+            };
+            environment.apply_package(&resolved_package.package, envs, &resolved_package.path, &expand_context)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct SolverState{
+    requirements: Vec<Requirement>,
+    selected: BTreeMap<String, Candidate>,
+}
+
+
+/// Computes a single consistent version for every package reachable from
+/// the main package and the appended packages.
+pub struct Solver<'a>{
+    vat_repos: &'a VatRepos,
+    pinned: HashMap<String, Candidate>,
+    packages: RefCell<HashMap<PathBuf, Package>>,
+}
+
+impl<'a> Solver<'a>{
+    pub fn new(vat_repos: &'a VatRepos) -> Self{
+        Self{vat_repos, pinned: HashMap::new(), packages: RefCell::new(HashMap::new())}
+    }
+
+
+    /// `main` can already hold a package read from disk (e.g. the current directory),
+    /// in which case that package is used as is instead of looking it up in the repository.
+    pub fn solve(&mut self, main: &PackageResolver, appends: &[PackageResolver]) -> Result<Resolution, anyhow::Error>{
+        if let (Some(package), Some(package_path)) = (&main.package, &main.package_path){
+            self.pinned.insert(main.package_name.clone(), Candidate{
+                version: package.get_version().clone(),
+                path: package_path.clone(),
+                main_branch: true,
+                fallback: true,
+                repository: None,
+            });
+            self.packages.borrow_mut().insert(package_path.clone(), package.clone());
+        }
+
+        let mut requirements = vec![Requirement::from_resolver(main, vec![])];
+        for append in appends{
+            requirements.push(Requirement::from_resolver(append, vec![]));
+        }
+
+        let state = SolverState{requirements, selected: BTreeMap::new()};
+        let state = match self.search(state)?{
+            Ok(state) => state,
+            Err(conflict) => return Err(anyhow!(conflict)),
+        };
+
+        let mut packages: Vec<ResolvedPackage> = vec![];
+        self.order(&state, &main.package_name, main, appends, &mut packages, &mut vec![])?;
+        for append in appends{
+            self.order(&state, &append.package_name, main, appends, &mut packages, &mut vec![])?;
+        }
+
+        for resolved_package in &packages{
+            if resolved_package.role == PackageRole::Dependency{
+                let message = format!("Dependency : {} - Version: {}", resolved_package.name, resolved_package.version);
+                output::status(message.green());
+            }
+        }
+
+        Ok(Resolution{packages})
+    }
+
+
+    /// Backtracking search, the outer error is for failures unrelated to versions
+    /// (a missing package, an unreadable vat.toml), the inner one is a version conflict.
+    fn search(&self, state: SolverState) -> Result<Result<SolverState, Conflict>, anyhow::Error>{
+        let next = state.requirements.iter().find(|r| !state.selected.contains_key(&r.package_name));
+        let package_name = match next{
+            Some(requirement) => requirement.package_name.clone(),
+            None => return Ok(Ok(state)),
+        };
+
+        let requirements = state.requirements.iter()
+            .filter(|r| r.package_name == package_name)
+            .cloned()
+            .collect::<Vec<Requirement>>();
+        for requirement in &requirements{
+            // an unknown `name@repository` is an error, not a conflict
+            self.vat_repos.search(requirement.repository.as_deref())?;
+        }
+
+        let mut last_conflict: Option<Conflict> = None;
+
+        for candidate in self.candidates(&package_name)?{
+            if !requirements.iter().all(|r| r.is_satisfied_by(&candidate)){
+                continue;
+            }
+
+            let package = self.read_package(&candidate.path)?;
+            let mut required_by = requirements[0].required_by.clone();
+            required_by.push(format!("{}/{}", package_name, candidate.version));
+
+            let mut new_state = state.clone();
+            new_state.selected.insert(package_name.clone(), candidate.clone());
+
+            let mut conflict: Option<Conflict> = None;
+            for dependency in package.get_dependencies(){
+                let dependency_resolver = match PackageResolver::parse_package_string(&dependency){
+                    Some(dependency_resolver) => dependency_resolver,
+                    None => {
+                        return Err(anyhow!("{}/{}: invalid dependency `{}`", package_name, candidate.version, dependency));
+                    }
+                };
+                let requirement = Requirement::from_resolver(&dependency_resolver, required_by.clone());
+
+                if let Some(selected) = new_state.selected.get(&requirement.package_name){
+                    if !requirement.is_satisfied_by(selected){
+                        let mut requirements = new_state.requirements.iter()
+                            .filter(|r| r.package_name == requirement.package_name)
+                            .cloned()
+                            .collect::<Vec<Requirement>>();
+                        requirements.push(requirement);
+                        conflict = Some(Conflict{package_name: dependency_resolver.package_name, requirements});
+                        break;
+                    }
+                }
+                new_state.requirements.push(requirement);
+            }
+
+            if let Some(conflict) = conflict{
+                last_conflict = Some(conflict);
+                continue;
+            }
+
+            match self.search(new_state)?{
+                Ok(state) => return Ok(Ok(state)),
+                Err(conflict) => last_conflict = Some(conflict),
+            }
+        }
+
+        match last_conflict{
+            Some(conflict) => Ok(Err(conflict)),
+            None => Ok(Err(Conflict{package_name, requirements})),
+        }
+    }
+
+
+    /// Available versions of a package, highest first, followed by its main branch.
+    /// A version published in several repositories is listed once per repository, in search order.
+    fn candidates(&self, package_name: &str) -> Result<Vec<Candidate>, anyhow::Error>{
+        if let Some(candidate) = self.pinned.get(package_name){
+            return Ok(vec![candidate.clone()]);
+        }
+
+        let mut versions: Vec<semver::Version> = vec![];
+        for vat_repo in &self.vat_repos.repositories{
+            for version in vat_repo.get_versions(package_name){
+                if !versions.contains(&version){
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort_by(|a, b| b.cmp(a));
+
+        let mut candidates = vec![];
+        for version in versions{
+            for vat_repo in &self.vat_repos.repositories{
+                if vat_repo.version_exists(package_name, &version){
+                    candidates.push(Candidate{
+                        version: version.clone(),
+                        path: vat_repo.get_package_version_path(package_name, &version),
+                        main_branch: false,
+                        fallback: false,
+                        repository: Some(vat_repo.name.clone()),
+                    });
+                }
+            }
+        }
+
+        let mut found = false;
+        for vat_repo in &self.vat_repos.repositories{
+            let repo_package = match vat_repo.get_repo_package(package_name){
+                Some(repo_package) => repo_package,
+                None => continue,
+            };
+            found = true;
+            let main_branch_path = repo_package.main_branch_path.clone();
+            if candidates.iter().any(|candidate| candidate.main_branch && candidate.path == main_branch_path){
+                continue;
+            }
+            if Package::is_vat_package(&main_branch_path){
+                let package = self.read_package(&main_branch_path)?;
+                candidates.push(Candidate{
+                    version: package.get_version().clone(),
+                    path: main_branch_path,
+                    main_branch: true,
+                    fallback: !candidates.iter().any(|candidate| !candidate.main_branch),
+                    repository: Some(vat_repo.name.clone()),
+                });
+            }
+        }
+
+        if !found{
+            return Err(anyhow!("Package {} not found", package_name));
+        }
+
+        Ok(candidates)
+    }
+
+
+    fn read_package(&self, package_path: &PathBuf) -> Result<Package, anyhow::Error>{
+        if let Some(package) = self.packages.borrow().get(package_path){
+            return Ok(package.clone());
+        }
+        let package = Package::read(package_path)?;
+        self.packages.borrow_mut().insert(package_path.clone(), package.clone());
+        Ok(package)
+    }
+
+
+    /// Depth first walk from `package_name`, pushing dependencies before their dependents
+    fn order(&self, state: &SolverState, package_name: &str, main: &PackageResolver, appends: &[PackageResolver], packages: &mut Vec<ResolvedPackage>, visiting: &mut Vec<String>) -> Result<(), anyhow::Error>{
+        if packages.iter().any(|p| p.name == package_name){
+            return Ok(());
+        }
+        if visiting.iter().any(|name| name == package_name){
+            let mut chain = visiting.clone();
+            chain.push(package_name.to_string());
+            return Err(anyhow!("Circular dependency: {}", chain.join(" -> ")));
+        }
+
+        let candidate = match state.selected.get(package_name){
+            Some(candidate) => candidate,
+            None => return Err(anyhow!("Package {} was not resolved", package_name)),
+        };
+        let package = self.read_package(&candidate.path)?;
+
+        visiting.push(package_name.to_string());
+        for dependency in package.get_dependencies(){
+            if let Some(dependency_resolver) = PackageResolver::parse_package_string(&dependency){
+                self.order(state, &dependency_resolver.package_name, main, appends, packages, visiting)?;
+            }
+        }
+        visiting.pop();
+
+        let role = if main.package_name == package_name{
+            PackageRole::Main
+        }else if appends.iter().any(|append| append.package_name == package_name){
+            PackageRole::Append
+        }else{
+            PackageRole::Dependency
+        };
+
+        packages.push(ResolvedPackage{
+            name: package_name.to_string(),
+            version: candidate.version.clone(),
+            path: candidate.path.clone(),
+            package,
+            env: Self::merge_env(&state.requirements, package_name),
+            role,
+            main_branch: candidate.main_branch,
+            repository: candidate.repository.clone(),
+        });
+        Ok(())
+    }
+
+
+    /// Selected environments of a package across all of its requirements,
+    /// any requirement without a selection means every environment is applied.
+    fn merge_env(requirements: &[Requirement], package_name: &str) -> Option<Vec<String>>{
+        let mut envs: Vec<String> = vec![];
+        for requirement in requirements.iter().filter(|r| r.package_name == package_name){
+            match &requirement.env{
+                Some(requirement_envs) => {
+                    for env in requirement_envs{
+                        if !envs.contains(env){
+                            envs.push(env.clone());
+                        }
+                    }
+                }
+                None => return None,
+            }
+        }
+        Some(envs)
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use chrono::Utc;
+    use crate::package::Dependencies;
+    use crate::vat_repository::{RepoPackage, RepoPackageInfo, VatRepo};
+
+    const REPO_PATH: &str = "/repo";
+
+    fn package(name: &str, version: &str, dependencies: &[&str]) -> Package{
+        let mut package = Package::default(name.to_string());
+        package.package_info.version = semver::Version::parse(version).unwrap();
+        package.dependencies = Some(Dependencies{dependencies: dependencies.iter().map(|d| d.to_string()).collect()});
+        package
+    }
+
+    /// Repository `site` publishing the packages, the solver reads them from its cache instead of disk
+    fn repository(published: &[Package], main_branches: &[(&str, PathBuf)]) -> (VatRepos, HashMap<PathBuf, Package>){
+        let mut vat_repo = VatRepo{packages: HashMap::new(), name: "site".to_string(), path: PathBuf::from(REPO_PATH)};
+        let mut packages = HashMap::new();
+        for (name, main_branch_path) in main_branches{
+            vat_repo.packages.insert(name.to_string(), RepoPackage{versions: HashMap::new(), main_branch_path: main_branch_path.clone(), git_url: None});
+        }
+        for package in published{
+            let repo_package = vat_repo.packages.entry(package.get_name().to_string()).or_insert_with(|| RepoPackage{
+                versions: HashMap::new(),
+                main_branch_path: PathBuf::from("/src").join(package.get_name()),
+                git_url: None,
+            });
+            repo_package.versions.insert(package.get_version().clone(), RepoPackageInfo{published_on: Utc::now(), version_comment: None});
+            packages.insert(vat_repo.get_package_version_path(package.get_name(), package.get_version()), package.clone());
+        }
+        (VatRepos{repositories: vec![vat_repo]}, packages)
+    }
+
+    fn solve(vat_repos: &VatRepos, packages: &HashMap<PathBuf, Package>, main: &str, appends: &[&str]) -> Result<Resolution, anyhow::Error>{
+        let mut solver = Solver::new(vat_repos);
+        solver.packages.borrow_mut().extend(packages.clone());
+        let main = PackageResolver::parse_package_string(main).unwrap();
+        let appends = appends.iter().map(|a| PackageResolver::parse_package_string(a).unwrap()).collect::<Vec<PackageResolver>>();
+        solver.solve(&main, &appends)
+    }
+
+    fn version(resolution: &Resolution, package_name: &str) -> String{
+        resolution.get(package_name).unwrap().version.to_string()
+    }
+
+    #[test]
+    fn conflicting_requirements_report_every_chain(){
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["tool_a", "tool_b"]),
+            package("tool_a", "1.2.0", &["usd>=24"]),
+            package("tool_b", "0.4.0", &["usd<23"]),
+            package("usd", "24.1.0", &[]),
+            package("usd", "22.0.0", &[]),
+        ], &[]);
+        let error = solve(&vat_repos, &packages, "app", &[]).unwrap_err().to_string();
+        assert!(error.starts_with("Conflicting requirements for usd:"), "{}", error);
+        assert!(error.contains("app/1.0.0 -> tool_a/1.2.0 -> usd>=24"), "{}", error);
+        assert!(error.contains("app/1.0.0 -> tool_b/0.4.0 -> usd<23"), "{}", error);
+    }
+
+    #[test]
+    fn backtracks_to_a_lower_version(){
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["tool_a", "tool_b"]),
+            package("tool_a", "1.2.0", &["usd>=24"]),
+            package("tool_b", "0.4.0", &["usd<23"]),
+            package("tool_b", "0.3.0", &["usd>=22"]),
+            package("usd", "24.1.0", &[]),
+            package("usd", "22.0.0", &[]),
+        ], &[]);
+        let resolution = solve(&vat_repos, &packages, "app", &[]).unwrap();
+        assert_eq!(version(&resolution, "tool_b"), "0.3.0");
+        assert_eq!(version(&resolution, "usd"), "24.1.0");
+    }
+
+    #[test]
+    fn highest_matching_version_is_selected(){
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["usd>=22,<24"]),
+            package("usd", "24.1.0", &[]),
+            package("usd", "23.5.0", &[]),
+            package("usd", "22.0.0", &[]),
+        ], &[]);
+        let resolution = solve(&vat_repos, &packages, "app", &[]).unwrap();
+        assert_eq!(version(&resolution, "usd"), "23.5.0");
+        assert_eq!(resolution.get("usd").unwrap().repository.as_deref(), Some("site"));
+    }
+
+    #[test]
+    fn unknown_package_and_repository_are_errors(){
+        let (vat_repos, packages) = repository(&[package("app", "1.0.0", &["missing"])], &[]);
+        let error = solve(&vat_repos, &packages, "app", &[]).unwrap_err().to_string();
+        assert_eq!(error, "Package missing not found");
+        let error = solve(&vat_repos, &packages, "app@show", &[]).unwrap_err().to_string();
+        assert_eq!(error, "Repository show not found");
+    }
+
+    #[test]
+    fn main_branch_is_a_fallback_until_a_version_is_published(){
+        let source = std::env::temp_dir().join(format!("vat-solver-{}", std::process::id()));
+        let mut main_branches = vec![];
+        for (name, version) in [("tool_c", "0.1.0"), ("tool_d", "2.0.0")]{
+            let path = source.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("vat.toml"), toml::to_string(&package(name, version, &[])).unwrap()).unwrap();
+            main_branches.push((name, path));
+        }
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["tool_c>=0.1", "tool_d"]),
+            package("tool_d", "1.0.0", &[]),
+        ], &main_branches);
+
+        let resolution = solve(&vat_repos, &packages, "app", &[]);
+        let main_resolution = solve(&vat_repos, &packages, "tool_d/main", &[]);
+        std::fs::remove_dir_all(&source).unwrap();
+
+        // nothing published for tool_c, its main branch is matched on the version of its vat.toml
+        let resolution = resolution.unwrap();
+        let tool_c = resolution.get("tool_c").unwrap();
+        assert!(tool_c.main_branch);
+        assert_eq!(tool_c.path, source.join("tool_c"));
+        // tool_d has a published version, its main branch is only used when asked for
+        let tool_d = resolution.get("tool_d").unwrap();
+        assert!(!tool_d.main_branch);
+        assert_eq!(tool_d.version.to_string(), "1.0.0");
+        let tool_d = main_resolution.unwrap();
+        assert!(tool_d.main().unwrap().main_branch);
+        assert_eq!(version(&tool_d, "tool_d"), "2.0.0");
+    }
+
+    #[test]
+    fn dependencies_are_ordered_before_their_dependents(){
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["tool_a", "tool_b"]),
+            package("tool_a", "1.0.0", &["usd"]),
+            package("tool_b", "1.0.0", &["usd"]),
+            package("usd", "24.1.0", &[]),
+            package("nuke", "15.1.0", &["ocio", "usd"]),
+            package("ocio", "2.3.0", &[]),
+        ], &[]);
+        let resolution = solve(&vat_repos, &packages, "app", &["nuke"]).unwrap();
+        let order = resolution.packages.iter()
+            .map(|p| (p.name.as_str(), p.role.clone()))
+            .collect::<Vec<(&str, PackageRole)>>();
+        assert_eq!(order, vec![
+            ("usd", PackageRole::Dependency),
+            ("tool_a", PackageRole::Dependency),
+            ("tool_b", PackageRole::Dependency),
+            ("app", PackageRole::Main),
+            ("ocio", PackageRole::Dependency),
+            ("nuke", PackageRole::Append),
+        ]);
+    }
+
+    #[test]
+    fn environments_of_every_requirement_are_merged(){
+        let (vat_repos, packages) = repository(&[
+            package("app", "1.0.0", &["tool_a", "usd[PATH]"]),
+            package("tool_a", "1.0.0", &["usd[PYTHONPATH, PATH]"]),
+            package("usd", "24.1.0", &[]),
+        ], &[]);
+        let resolution = solve(&vat_repos, &packages, "app", &[]).unwrap();
+        assert_eq!(resolution.get("usd").unwrap().env, Some(vec!["PATH".to_string(), "PYTHONPATH".to_string()]));
+        assert_eq!(resolution.get("tool_a").unwrap().env, None);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::VatConfig;
 use std::path::PathBuf;
-use std::fs;
+use crate::atomic;
+use crate::lock::FileLock;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
@@ -20,33 +21,55 @@ impl Stacks{
 
     pub fn init() -> Result<Self, anyhow::Error>{
         let config_path = Stacks::stacks_config_path();
+        if !config_path.exists(){
+            let _lock = FileLock::exclusive(&config_path)?;
+            // another process may have created it while this one waited for the lock
+            if !config_path.exists(){
+                let config = Stacks{stacks: HashMap::new(), order: vec![]};
+                let config_str = toml::to_string(&config).unwrap();
+                atomic::write(&config_path, &config_str)?;
+                return Ok(config);
+            }
+        }
+        let config = atomic::read(&config_path, Self::parse)?;
+        Ok(config)
+    }
+
+    fn parse(config_str: &str) -> Result<Self, anyhow::Error>{
+        Ok(toml::from_str::<Stacks>(config_str)?)
+    }
+
+    /// Lock the stacks for a load-modify-save cycle and reload them,
+    /// they are released when the returned lock is dropped
+    pub fn lock(&mut self) -> Result<FileLock, anyhow::Error>{
+        let config_path = Stacks::stacks_config_path();
+        let lock = FileLock::exclusive(&config_path)?;
         if config_path.exists(){
-            let config = toml::from_str(&fs::read_to_string(config_path).unwrap()).unwrap();
-            Ok(config)
-        }else{
-            let config = Stacks{stacks: HashMap::new(), order: vec![]};
-            let config_str = toml::to_string(&config).unwrap();
-            fs::write(config_path, config_str).unwrap();
-            Ok(config)
+            let stacks = atomic::read_or_restore(&config_path, Self::parse)?;
+            self.stacks = stacks.stacks;
+            self.order = stacks.order;
         }
+        Ok(lock)
     }
 
     pub fn save(&self) -> Result<(), anyhow::Error>{
         let config_path = Stacks::stacks_config_path();
         let config_str = toml::to_string(&self).unwrap();
-        fs::write(config_path, config_str).unwrap();
+        atomic::write(&config_path, &config_str)?;
         Ok(())
     }
 
     pub fn save_as(stacks: &Stacks) -> Result<(), anyhow::Error>{
         let config_path = Stacks::stacks_config_path();
+        let _lock = FileLock::exclusive(&config_path)?;
         let config_str = toml::to_string(&stacks).unwrap();
-        fs::write(config_path, config_str).unwrap();
+        atomic::write(&config_path, &config_str)?;
         Ok(())
     }
 
 
     pub fn append_stack(&mut self, stack: Stack) -> Result<(), anyhow::Error>{
+        let _lock = self.lock()?;
         let name = stack.name.clone();
         self.stacks.insert(name.clone(), stack);
         self.order.push(name);
@@ -55,13 +78,16 @@ impl Stacks{
     }
 
     pub fn remove_stack(&mut self, name: &str) -> Result<(), anyhow::Error>{
+        let _lock = self.lock()?;
         self.stacks.remove(name);
-        self.order.remove(self.order.iter().position(|r| r == name).unwrap());
+        // another process may have removed it since the stacks were loaded
+        self.order.retain(|r| r != name);
         self.save()?;
         Ok(())
     }
 
     pub fn update_stack(&mut self, name: &str, stack: Stack) -> Result<(), anyhow::Error>{
+        let _lock = self.lock()?;
         self.stacks.insert(name.to_string(), stack);
         self.save()?;
         Ok(())
@@ -5,8 +5,10 @@ use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use anyhow::{anyhow, Result};
 use crate::package::{self, Package};
-use crate::package::{PackageResolver, PackageFrom};
 use colored::Colorize;
+use crate::output;
+use crate::atomic;
+use crate::lock::FileLock;
 use chrono::{DateTime, Utc};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -50,45 +52,73 @@ impl RepoPackage{
     }
 }
 
+/// A package of the repositories as listed by `vat repo --json`
+#[derive(Serialize, Debug, Clone)]
+pub struct PackageListing{
+    pub name: String,
+    pub main_branch_path: PathBuf,
+    pub git_url: Option<String>,
+    pub versions: Vec<VersionListing>,
+}
+
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VersionListing{
+    pub version: semver::Version,
+    pub published_on: DateTime<Utc>,
+    pub version_comment: Option<String>,
+    /// name of the repository the version is resolved from
+    pub repository: String,
+}
+
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 
 pub struct VatRepo{
-    pub packages: HashMap<String, RepoPackage>
+    pub packages: HashMap<String, RepoPackage>,
+    /// name of the repository in the config
+    #[serde(skip)]
+    pub name: String,
+    /// directory holding `vat.repository.toml` and the published versions
+    #[serde(skip)]
+    pub path: PathBuf,
 }
 
 
 impl VatRepo{
     pub fn new() -> Self{
-        Self{packages: HashMap::new()}
+        Self{packages: HashMap::new(), name: String::new(), path: PathBuf::new()}
     }
 
 
+    /// The repository packages are published and linked to
     pub fn init() -> Result<Self, anyhow::Error>{
 
         let vat_config = VatConfig::init()?;
-        let repository_path = vat_config.get_repository_path();
+        match vat_config.get_publish_repository(){
+            Some(repository) => Self::open(&repository.name, &repository.path),
+            None => Err(anyhow!("Repository path not found")),
+        }
+    }
 
-        let repository_path = match repository_path{
-            Some(path) => path,
-            None => return Err(anyhow!("Repository path not found")),
-        };
+
+    pub fn open(name: &str, repository_path: &PathBuf) -> Result<Self, anyhow::Error>{
 
         if !repository_path.exists(){
             fs::create_dir_all(&repository_path)?;
         }
 
         let repository_config_path = repository_path.join("vat.repository.toml");
-        let repository = if !repository_config_path.exists(){
-            let repository = VatRepo::new();
-
-            let repository_config_str = toml::to_string(&repository)?;
-            fs::write(repository_config_path, repository_config_str)?;
-            repository  
-        }else{
-            let repository_config_str = fs::read_to_string(repository_config_path)?;
-            let repository: VatRepo = toml::from_str(&repository_config_str)?;
-            repository
-        }; 
+        if !repository_config_path.exists(){
+            let _lock = FileLock::exclusive(&repository_config_path)?;
+            if !repository_config_path.exists(){
+                let repository_config_str = toml::to_string(&VatRepo::new())?;
+                atomic::write(&repository_config_path, &repository_config_str)?;
+            }
+        }
+        let mut repository = atomic::read(&repository_config_path, Self::parse_index)?;
+        repository.name = name.to_string();
+        repository.path = repository_path.clone();
 
 
         Ok(repository)
@@ -97,6 +127,24 @@ impl VatRepo{
 
 
 
+    fn parse_index(repository_config_str: &str) -> Result<Self, anyhow::Error>{
+        Ok(toml::from_str::<VatRepo>(repository_config_str)?)
+    }
+
+
+    /// Lock the repository for a load-modify-save cycle and reload the packages,
+    /// changes published by other processes since the repository was opened are kept.
+    /// The repository is released when the returned lock is dropped.
+    pub fn lock(&mut self) -> Result<FileLock, anyhow::Error>{
+        let repository_config_path = self.path.join("vat.repository.toml");
+        let lock = FileLock::exclusive(&repository_config_path)?;
+        if repository_config_path.exists(){
+            self.packages = atomic::read_or_restore(&repository_config_path, Self::parse_index)?.packages;
+        }
+        Ok(lock)
+    }
+
+
     pub fn get_repo_package(&self, package_name: &str) -> Option<&RepoPackage>{
         self.packages.get(package_name)
     }
@@ -110,11 +158,11 @@ impl VatRepo{
         let package_name = package.get_name();
         let package_repository_path = package.package_info.repository.clone();
 
-        dbg!(&package_path);
         if !package_path.exists(){
             return Err(anyhow!("Package path not found"));
         }
 
+        let _lock = self.lock()?;
         if self.package_exists(&package_path)?{
             return Err(anyhow!("Package already exists"));
         }
@@ -131,13 +179,9 @@ impl VatRepo{
         self.save()?;
 
         // create a package directory in the repository
-        let vat_config = VatConfig::init()?;
-        let repository_path = vat_config.get_repository_path();
-        if let Some(repository_path) = repository_path{
-            let repository_package_path = repository_path.join(package_name);
-            if !repository_package_path.exists(){
-                fs::create_dir_all(&repository_package_path)?;
-            }
+        let repository_package_path = self.path.join(package_name);
+        if !repository_package_path.exists(){
+            fs::create_dir_all(&repository_package_path)?;
         }
 
         Ok(())
@@ -155,6 +199,7 @@ impl VatRepo{
             return Err(anyhow!("Package path not found"));
         }
 
+        let _lock = self.lock()?;
         // return an error if a different package exists with the same name
         let _package_exists_with_same_name = self.package_exists(&package_path)?;
 
@@ -181,7 +226,7 @@ impl VatRepo{
         repo_package.add_version(current_version, version_comment)?;
 
         // resolves path to copy the package to the repository
-        let repository_base_path = VatConfig::init()?.get_repository_path().unwrap();
+        let repository_base_path = self.path.clone();
         let repo_package_path = repository_base_path.join(package_name);
         let repo_package_version_path = repo_package_path.join(current_version.to_string());
 
@@ -189,7 +234,7 @@ impl VatRepo{
         let source_zip_file_path = package_path.join(&zip_file_name);
 
         let message = format!("Creating zip from the tag: {}", current_version);
-        println!("{}", message.bright_black());
+        output::status(message.bright_black());
 
         // create zip from git version
         // "git archive --format=zip -o archive.zip 0.0.3"
@@ -215,14 +260,14 @@ impl VatRepo{
         }
 
         let message = format!("Copying zip file to repository");
-        println!("{}", message.bright_black());
+        output::status(message.bright_black());
 
         let file = std::fs::File::open(&source_zip_file_path)?;
         let mut archive = zip::read::ZipArchive::new(file)?;
 
         archive.extract(repo_package_version_path)?;
         let message = format!("Version {} successfully extracted to repository", current_version);
-        println!("{}", message.bright_black());
+        output::status(message.bright_black());
 
         fs::remove_file(&source_zip_file_path)?;
 
@@ -231,7 +276,7 @@ impl VatRepo{
         self.save()?;
 
         let messsage = format!("{}: Version {} published", package_name, current_version);
-        println!("{}", messsage.cyan());
+        output::status(messsage.cyan());
 
         Ok(())
     }
@@ -254,124 +299,165 @@ impl VatRepo{
 
 
     pub fn save(&self) -> Result<(), anyhow::Error>{
-        let vat_config = VatConfig::init()?;
-        let repository_path = vat_config.get_repository_path();
-
-        let repository_path = match repository_path{
-            Some(path) => path,
-            None => return Err(anyhow!("Repository path not found")),
-        };
-
-        let repository_config_path = repository_path.join("vat.repository.toml");
+        let repository_config_path = self.path.join("vat.repository.toml");
         let repository_config_str = toml::to_string(self)?;
-        fs::write(repository_config_path, repository_config_str)?;
+        atomic::write(&repository_config_path, &repository_config_str)?;
         Ok(())  
     }
 
-    pub fn get_package(&self,package_resolver: &PackageResolver) -> Result<PackageResolver, anyhow::Error> {
-        let package_name = package_resolver.package_name.clone();
-        if !self.packages.contains_key(&package_name){
-
-            return Err(anyhow::anyhow!("Package {} not found", package_name));
+    pub fn get_latest_version(&self, package_name: &str) -> Option<semver::Version>{
+        let repo_package = self.get_repo_package(package_name)?;
+        let latest_version = repo_package.versions.keys().max();
+        latest_version.cloned()
+    }
 
+    /// Published versions of the package, highest first
+    pub fn get_versions(&self, package_name: &str) -> Vec<semver::Version>{
+        match self.get_repo_package(package_name){
+            Some(repo_package) => {
+                let mut versions = repo_package.versions.keys().cloned().collect::<Vec<semver::Version>>();
+                versions.sort_by(|a, b| b.cmp(a));
+                versions
+            }
+            None => vec![],
         }
+    }
 
-        let repo_path = VatConfig::init()?.get_repository_path().unwrap();
+    pub fn get_package_version_path(&self, package_name: &str, version: &semver::Version) -> PathBuf{
+        self.path.join(package_name).join(version.to_string())
+    }
 
-        let mut out_package_resolver = package_resolver.clone();
+    /// Highest published version of the package satisfying the requirement
+    pub fn get_matching_version(&self, package_name: &str, requirement: &semver::VersionReq) -> Option<semver::Version>{
+        let repo_package = self.get_repo_package(package_name)?;
+        let matching_version = repo_package.versions.keys()
+            .filter(|version| requirement.matches(version))
+            .max();
+        matching_version.cloned()
+    }
 
-        let package_path = match &package_resolver.from{
-            PackageFrom::Latest => {
-                let latest_version = self.get_latest_version(&package_name);
+    pub fn version_exists(&self, package_name: &str, version: &semver::Version) -> bool{
+        let repo_package = self.get_repo_package(package_name);
+        if repo_package.is_some(){
+            repo_package.unwrap().version_exists(version)
+        }else{
+            false
+        }
+    }
 
-                if latest_version.is_some(){
 
-                    let package_path = repo_path.join(package_name).join(latest_version.unwrap().to_string());
-                    package_path
 
+}
 
-                }else{
-                    // fall back to main branch
-                    let package_path = self.get_repo_package(&package_name).unwrap().main_branch_path.clone();
-                    package_path
-                }
-            }
 
-            PackageFrom::Version(version) => {
-                // package_path = repo_path.join(package_name).join(version.to_string());
-                if self.version_exists(&package_name, version){
-                    let package_path = repo_path.join(package_name).join(version.to_string());
-                    package_path
-                }else{
-                    // fall back to latest version
-
-                    let latest_version = self.get_latest_version(&package_name);
-                    if latest_version.is_some(){
-                        let package_path = repo_path.join(package_name).join(latest_version.unwrap().to_string());
-                        package_path
-                    }else{
-                        return Err(anyhow::anyhow!("Version not found"));
-                    }
 
-                }
-            }
+/// Every repository of the config, in search order.
+///
+/// A version is resolved from the first repository holding it, `name@repository`
+/// restricts a request to a single repository.
+pub struct VatRepos{
+    pub repositories: Vec<VatRepo>,
+}
 
-            PackageFrom::Main => {
-                let package_path = self.get_repo_package(&package_name).unwrap().main_branch_path.clone();
-                package_path
-            }
+impl VatRepos{
 
+    pub fn init() -> Result<Self, anyhow::Error>{
+        let vat_config = VatConfig::init()?;
+        let mut repositories = vec![];
+        for repository in vat_config.get_repositories(){
+            repositories.push(VatRepo::open(&repository.name, &repository.path)?);
+        }
+        if repositories.is_empty(){
+            return Err(anyhow!("Repository path not found"));
+        }
+        Ok(Self{repositories})
+    }
 
-        };
 
-        // dbg!(&package_path);
-        out_package_resolver.package_path = Some(package_path.clone());
-        let package = Package::read(&package_path)?;
-        out_package_resolver.package = Some(package);
-        Ok(out_package_resolver)
+    pub fn get_repository(&self, name: &str) -> Option<&VatRepo>{
+        self.repositories.iter().find(|repository| repository.name == name)
     }
 
 
+    /// Repositories to search in order, only `repository` when it is given
+    pub fn search(&self, repository: Option<&str>) -> Result<Vec<&VatRepo>, anyhow::Error>{
+        match repository{
+            Some(name) => match self.get_repository(name){
+                Some(repository) => Ok(vec![repository]),
+                None => Err(anyhow!("Repository {} not found", name)),
+            },
+            None => Ok(self.repositories.iter().collect()),
+        }
+    }
 
 
-    pub fn get_latest_version(&self, package_name: &str) -> Option<semver::Version>{
-        let repo_package = self.get_repo_package(package_name)?;
-        let latest_version = repo_package.versions.keys().max();
-        latest_version.cloned()
+    /// The package from the first repository holding it
+    pub fn get_repo_package(&self, package_name: &str, repository: Option<&str>) -> Option<&RepoPackage>{
+        let repositories = self.search(repository).ok()?;
+        repositories.into_iter().find_map(|repository| repository.get_repo_package(package_name))
     }
 
-    pub fn version_exists(&self, package_name: &str, version: &semver::Version) -> bool{
-        let repo_package = self.get_repo_package(package_name);
-        if repo_package.is_some(){
-            repo_package.unwrap().version_exists(version)
-        }else{
-            false
+
+    pub fn version_exists(&self, package_name: &str, version: &semver::Version, repository: Option<&str>) -> bool{
+        match self.search(repository){
+            Ok(repositories) => repositories.iter().any(|repository| repository.version_exists(package_name, version)),
+            Err(_) => false,
         }
     }
 
 
-    pub fn pretty_list(&self) {
-        if !self.packages.is_empty() {
-            for (package_name, package_versions) in &self.packages {
-                let message = format!("Package: {}", package_name);
-                println!("{}", message.green());
-                let mut sorted_versions = package_versions.versions.iter()
-                    .collect::<Vec<(&semver::Version, &RepoPackageInfo)>>();    
-
-                // sort the versions in reverse order
-                sorted_versions.sort_by(|a, b| b.0.cmp(a.0));
-                // sorted_versions.reverse();
-
-                for (version, package_info) in sorted_versions {
-                    println!("   {} - {} - {}", version, package_info.version_comment.clone().unwrap_or_default().bright_black(),  package_info.published_on.format("%Y-%m-%d %H:%M:%S").to_string().bright_black());
+    /// Packages of every repository sorted by name, each version listed once with the
+    /// repository it is resolved from, newest first
+    pub fn listing(&self) -> Vec<PackageListing>{
+        let mut listing: Vec<PackageListing> = vec![];
+        for repository in &self.repositories{
+            for (package_name, repo_package) in &repository.packages{
+                let index = match listing.iter().position(|package| &package.name == package_name){
+                    Some(index) => index,
+                    None => {
+                        listing.push(PackageListing{
+                            name: package_name.clone(),
+                            main_branch_path: repo_package.main_branch_path.clone(),
+                            git_url: repo_package.git_url.clone(),
+                            versions: vec![],
+                        });
+                        listing.len() - 1
+                    }
+                };
+                for (version, package_info) in &repo_package.versions{
+                    // shadowed by a repository earlier in the search order
+                    if listing[index].versions.iter().any(|listed| &listed.version == version){
+                        continue;
+                    }
+                    listing[index].versions.push(VersionListing{
+                        version: version.clone(),
+                        published_on: package_info.published_on,
+                        version_comment: package_info.version_comment.clone(),
+                        repository: repository.name.clone(),
+                    });
                 }
             }
-        } else {
-            println!("{}", format!("No packages found in the repository"));
         }
+        for package in &mut listing{
+            package.versions.sort_by(|a, b| b.version.cmp(&a.version));
+        }
+        listing.sort_by(|a, b| a.name.cmp(&b.name));
+        listing
     }
 
-}
-
-
 
+    pub fn pretty_list(&self) {
+        let listing = self.listing();
+        if listing.is_empty(){
+            println!("No packages found in the repositories");
+            return;
+        }
+        for package in listing{
+            let message = format!("Package: {}", package.name);
+            println!("{}", message.green());
+            for version in package.versions{
+                println!("   {} - {} - {} - {}", version.version, version.version_comment.unwrap_or_default().bright_black(), version.published_on.format("%Y-%m-%d %H:%M:%S").to_string().bright_black(), version.repository.cyan());
+            }
+        }
+    }
+}